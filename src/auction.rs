@@ -0,0 +1,260 @@
+//! English auctions of tokens held by the contract, for the admin-reserved
+//! legendary characters. Bids arrive as cw20 Sends of the payment token and
+//! the outbid bidder is refunded in the same transaction.
+use cosmwasm_std::{Addr, CosmosMsg, Deps, DepsMut, Env, MessageInfo, Order, Response, StdError, StdResult, Timestamp, Uint128};
+use cw_storage_plus::Bound;
+use terraswap::asset::{Asset, AssetInfo};
+
+use crate::contract::{
+    assert_movable, assert_transfers_allowed, checked_plus_seconds, collect_payment, is_soulbound, mint_from_reserve,
+    move_token, record_sale,
+};
+use crate::errors::ContractError;
+use crate::events::{auction_event, BIDDER, PAYMENT_TOKEN, PRICE};
+use crate::msg::{AuctionInfo, AuctionLot, AuctionsResponse, MintMsg};
+use crate::roles::assert_role;
+use crate::state::{Auction, Bid, LootopiaNFTContract, Role, TokenBalance, AUCTIONS, CONFIG};
+
+// bids in the last 10 minutes push the end back by default
+const DEFAULT_TIME_EXTENSION: u64 = 600;
+
+#[allow(clippy::too_many_arguments)]
+pub fn start_auction(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    lot: AuctionLot,
+    reserve_price: Uint128,
+    min_increment: Uint128,
+    end_time: Timestamp,
+    time_extension: Option<u64>,
+) -> Result<Response, ContractError> {
+    assert_role(deps.storage, &info.sender, Role::Admin)?;
+    if end_time <= env.block.time {
+        return Err(ContractError::Expired {});
+    }
+    if min_increment.is_zero() {
+        return Err(ContractError::InvalidConfig {
+            reason: "min_increment must be positive".to_string(),
+        });
+    }
+    // an extension can't outlast the auction itself
+    let length = end_time.seconds() - env.block.time.seconds();
+    let time_extension = match time_extension {
+        Some(time_extension) if time_extension > length => {
+            return Err(ContractError::InvalidConfig {
+                reason: "time_extension must not exceed the auction length".to_string(),
+            })
+        }
+        Some(time_extension) => time_extension,
+        None => DEFAULT_TIME_EXTENSION.min(length),
+    };
+    let config = CONFIG.load(deps.storage)?;
+    let minted = matches!(lot, AuctionLot::Mint { .. });
+    let (token_id, response) = match lot {
+        AuctionLot::Mint { extension } => mint_from_reserve(
+            deps.branch(),
+            env.clone(),
+            MintMsg {
+                token_id: String::new(),
+                owner: env.contract.address.to_string(),
+                token_uri: None,
+                extension,
+            },
+        )?,
+        AuctionLot::Token { token_id } => {
+            let token = LootopiaNFTContract::default().tokens.load(deps.storage, &token_id)?;
+            if token.owner != info.sender {
+                return Err(ContractError::Unauthorized {});
+            }
+            assert_movable(deps.storage, &env, &token_id)?;
+            move_token(deps.storage, &token_id, &env.contract.address)?;
+            (token_id, Response::new())
+        }
+    };
+    let auction = Auction {
+        payment_token: config.payment_token,
+        reserve_price,
+        min_increment,
+        end_time,
+        time_extension,
+        highest_bid: None,
+        minted,
+    };
+    AUCTIONS.save(deps.storage, &token_id, &auction)?;
+    Ok(response
+        .add_attribute("action", "start_auction")
+        .add_attribute("token_id", &token_id)
+        .add_event(
+            auction_event("start", &token_id)
+                .add_attribute(PAYMENT_TOKEN, &auction.payment_token)
+                .add_attribute("reserve_price", reserve_price)
+                .add_attribute("min_increment", min_increment)
+                .add_attribute("end_time", end_time.to_string()),
+        ))
+}
+
+fn load_auction(deps: Deps, token_id: &str) -> Result<Auction, ContractError> {
+    AUCTIONS
+        .may_load(deps.storage, token_id)?
+        .ok_or_else(|| ContractError::AuctionNotFound {
+            token_id: token_id.to_string(),
+        })
+}
+
+// the lot leaves the contract like a transfer, unless it was minted for the auction
+fn assert_lot_movable(deps: Deps, auction: &Auction, token_id: &str) -> Result<(), ContractError> {
+    assert_transfers_allowed(deps.storage)?;
+    if !auction.minted && is_soulbound(deps.storage, token_id)? {
+        return Err(ContractError::Soulbound {
+            token_id: token_id.to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn pay(deps: Deps, token: &str, amount: Uint128, recipient: Addr) -> StdResult<CosmosMsg> {
+    let to_pay = Asset {
+        info: AssetInfo::Token {
+            contract_addr: token.to_string(),
+        },
+        amount,
+    };
+    to_pay.into_msg(&deps.querier, recipient)
+}
+
+pub fn bid(
+    deps: DepsMut,
+    env: Env,
+    bidder: String,
+    token_id: String,
+    payment: TokenBalance,
+) -> Result<Response, ContractError> {
+    let mut auction = load_auction(deps.as_ref(), &token_id)?;
+    if env.block.time >= auction.end_time {
+        return Err(ContractError::AuctionEnded {});
+    }
+    if payment.token != auction.payment_token {
+        return Err(ContractError::InvalidPaymentToken {
+            expected: auction.payment_token,
+            got: payment.token,
+        });
+    }
+    let required = match &auction.highest_bid {
+        Some(highest_bid) => highest_bid.amount.checked_add(auction.min_increment).map_err(StdError::from)?,
+        None => auction.reserve_price,
+    };
+    if payment.amount < required {
+        return Err(ContractError::InsufficientPayment {
+            required,
+            sent: payment.amount,
+        });
+    }
+
+    let bidder = deps.api.addr_validate(&bidder)?;
+    let mut response = Response::new();
+    if let Some(outbid) = auction.highest_bid.take() {
+        response = response.add_message(pay(deps.as_ref(), &auction.payment_token, outbid.amount, outbid.bidder)?);
+    }
+    // late bids leave the others time to answer
+    let extended_end = checked_plus_seconds(env.block.time, auction.time_extension)?;
+    if extended_end > auction.end_time {
+        auction.end_time = extended_end;
+    }
+    auction.highest_bid = Some(Bid {
+        bidder: bidder.clone(),
+        amount: payment.amount,
+    });
+    AUCTIONS.save(deps.storage, &token_id, &auction)?;
+    Ok(response
+        .add_attribute("action", "bid")
+        .add_attribute("token_id", &token_id)
+        .add_event(
+            auction_event("bid", &token_id)
+                .add_attribute(BIDDER, bidder)
+                .add_attribute(PAYMENT_TOKEN, &payment.token)
+                .add_attribute(PRICE, payment.amount)
+                .add_attribute("end_time", auction.end_time.to_string()),
+        ))
+}
+
+// only before the first bid, the token goes back to the admin
+pub fn cancel_auction(deps: DepsMut, info: MessageInfo, token_id: String) -> Result<Response, ContractError> {
+    assert_role(deps.storage, &info.sender, Role::Admin)?;
+    let auction = load_auction(deps.as_ref(), &token_id)?;
+    if auction.highest_bid.is_some() {
+        return Err(ContractError::AuctionHasBids {});
+    }
+    assert_lot_movable(deps.as_ref(), &auction, &token_id)?;
+    AUCTIONS.remove(deps.storage, &token_id);
+    let admin = deps.api.addr_validate(&CONFIG.load(deps.storage)?.admin)?;
+    move_token(deps.storage, &token_id, &admin)?;
+    Ok(Response::new()
+        .add_attribute("action", "cancel_auction")
+        .add_attribute("token_id", &token_id)
+        .add_event(auction_event("cancel", &token_id)))
+}
+
+pub fn settle_auction(mut deps: DepsMut, env: Env, token_id: String) -> Result<Response, ContractError> {
+    let auction = load_auction(deps.as_ref(), &token_id)?;
+    if env.block.time < auction.end_time {
+        return Err(ContractError::AuctionNotEnded {
+            end_time: auction.end_time,
+        });
+    }
+    assert_lot_movable(deps.as_ref(), &auction, &token_id)?;
+    AUCTIONS.remove(deps.storage, &token_id);
+    let config = CONFIG.load(deps.storage)?;
+    let mut event = auction_event("settle", &token_id);
+    let mut response = Response::new();
+    match auction.highest_bid {
+        Some(winning_bid) => {
+            move_token(deps.storage, &token_id, &winning_bid.bidder)?;
+            record_sale(
+                deps.storage,
+                winning_bid.bidder.as_str(),
+                &auction.payment_token,
+                winning_bid.amount,
+            )?;
+            response = response.add_messages(collect_payment(
+                deps.branch(),
+                &config,
+                &auction.payment_token,
+                winning_bid.amount,
+            )?);
+            event = event
+                .add_attribute(BIDDER, winning_bid.bidder)
+                .add_attribute(PAYMENT_TOKEN, &auction.payment_token)
+                .add_attribute(PRICE, winning_bid.amount);
+        }
+        // no bid met the reserve price
+        None => {
+            let admin = deps.api.addr_validate(&config.admin)?;
+            move_token(deps.storage, &token_id, &admin)?;
+        }
+    }
+    Ok(response
+        .add_attribute("action", "settle_auction")
+        .add_attribute("token_id", &token_id)
+        .add_event(event))
+}
+
+pub fn query_auctions(deps: Deps, start_after: Option<String>, limit: Option<u32>) -> StdResult<AuctionsResponse> {
+    const MAX_LIMIT: u32 = 30;
+    const DEFAULT_LIMIT: u32 = 10;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+
+    let auctions: StdResult<Vec<_>> = AUCTIONS
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (token_id, auction) = item?;
+            Ok(AuctionInfo {
+                token_id: String::from_utf8(token_id)?,
+                auction,
+            })
+        })
+        .collect();
+    Ok(AuctionsResponse { auctions: auctions? })
+}
@@ -1,13 +1,27 @@
 use crate::state::{LootopiaNFTContract}; 
 use cosmwasm_std::entry_point;
-use cosmwasm_std::{to_binary, from_binary, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdResult, Uint128, Order};
+use cosmwasm_std::{to_binary, from_binary, Addr, Binary, CosmosMsg, Decimal, Deps, DepsMut, Env, MessageInfo, Response, StdError, StdResult, Storage, Timestamp, Uint128, Uint64, Order};
 
-use cw2::{get_contract_version, set_contract_version};
+use cw2::set_contract_version;
 use cw20::{Cw20ReceiveMsg};
 pub use cw721_base::{MinterResponse};
-use crate::msg::{ExecuteMsg, InstantiateMsg, QueryMsg, MigrateMsg, ReceiveMsg, MintMsg, AllWhitelisted};
+use cw721_base::state::TokenInfo;
+use crate::msg::{ExecuteMsg, InstantiateMsg, QueryMsg, MigrateMsg, ReceiveMsg, NftReceiveMsg, RefundInfoResponse, SupplyInfoResponse, SoulboundResponse, LockStatusResponse, MintMsg, AllWhitelisted, CharacterSheetResponse, AdminProposalsResponse, RolesResponse, RoleMembersResponse, UpdateConfigMsg, SalesBalanceResponse, LineageResponse, TokenWithInfo, TokensWithInfoResponse};
 use crate::errors::ContractError;
-use crate::state::{Config, CONFIG, Loot, LOOT, Metadata, Trait, MINTS_BY_ADDRESS, WHITELIST_BY_ADDRESS, Extension};
+use crate::state::{Config, CONFIG, Loot, LOOT, Metadata, Trait, MINTS_BY_ADDRESS, WHITELIST_BY_ADDRESS, Extension, SvgStyle, SVG_STYLE, AdminProposal, ADMIN_PROPOSAL, WHITELIST_ADMIN_PROPOSAL, Role, ROLES, PauseFlags, PAUSE_FLAGS, SALES_BALANCE, TokenBalance, SalesStats, SALES_STATS, BuyerStats, BUYER_STATS, RefundConfig, REFUND_CONFIG, REFUND_RESERVE, Purchase, PURCHASES, SOULBOUND_TOKENS, TokenLock, LOCKS, LOCKERS, PROGRESSION, LEVEL_CONFIG, FUSION_CONFIG, LINEAGE, TOKENS, LISTINGS, MARKET_CONFIG, AUCTIONS, USERS, RENTAL_LISTINGS};
+use crate::roles::{assert_role, grant_role, revoke_role, roles_of};
+use cw721::{AllNftInfoResponse, Cw721Query, NftInfoResponse, Cw721ReceiveMsg, Expiration};
+use cw_storage_plus::{Item, Map};
+use crate::svg::{render_character_sheet, validate_style};
+use crate::migrations::{self, CONTRACT_NAME, CONTRACT_VERSION};
+use crate::progression::{self, with_level};
+use crate::equipment;
+use crate::fusion;
+use crate::market;
+use crate::auction;
+use crate::rental;
+use crate::traits::{self, index_traits, unindex_traits};
+use crate::events::{self, admin_event, buy_event, config_change, config_event, metadata_event, pause_event, refund_event, role_event, soulbound_event, lock_event, whitelist_event, withdraw_event};
 use terraswap::asset::{Asset, AssetInfo};
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
@@ -15,11 +29,14 @@ use cw_storage_plus::Bound;
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
-    deps: DepsMut,
+    mut deps: DepsMut,
     env: Env,
     info: MessageInfo,
     msg: InstantiateMsg,
 ) -> StdResult<Response> {
+    if msg.num_reserved.unwrap_or(0) > msg.num_items {
+        return Err(StdError::generic_err("num_reserved exceeds num_items"));
+    }
     LOOT.save(
         deps.storage,
         &Loot {
@@ -32,8 +49,13 @@ pub fn instantiate(
             alignments: msg.alignments.clone(),
             num_items: msg.num_items,
             curr_num_items: 0,
+            num_reserved: msg.num_reserved.unwrap_or(0),
+            reserved_minted: 0,
+            fused: 0,
+            refunded: 0,
         },
     )?;
+
     let config = Config {
         payment_token: msg.payment_token.clone(),
         price: msg.price,
@@ -42,12 +64,27 @@ pub fn instantiate(
         whitelist: msg.whitelist,
         whitelist_admin: msg.whitelist_admin.clone(),
         admin: msg.admin.clone(),
+        free_mint: msg.price.is_zero(),
+        escrow: msg.escrow.unwrap_or(false),
+        sale_start: msg.sale_start,
+        soulbound: msg.soulbound.unwrap_or(false),
     };
 
     CONFIG.save(deps.storage, &config)?;
+    let svg_style = msg.svg_style.clone().unwrap_or_default();
+    validate_style(&svg_style).map_err(|err| StdError::generic_err(err.to_string()))?;
+    SVG_STYLE.save(deps.storage, &svg_style)?;
+
+    let admin = deps.api.addr_validate(&msg.admin)?;
+    grant_role(deps.storage, &admin, Role::Admin)?;
+    let whitelist_admin = deps.api.addr_validate(&msg.whitelist_admin)?;
+    grant_role(deps.storage, &whitelist_admin, Role::WhitelistManager)?;
 
 
-    LootopiaNFTContract::default().instantiate(deps, env, info, msg.into())
+    // cw721-base sets its own contract version, which is replaced by ours
+    let response = LootopiaNFTContract::default().instantiate(deps.branch(), env, info, msg.into())?;
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+    Ok(response)
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
@@ -57,17 +94,93 @@ pub fn execute(
     info: MessageInfo,
     msg: ExecuteMsg,
 ) -> Result<Response, ContractError> {
+    assert_not_paused(deps.storage, &msg)?;
+    assert_transferable(deps.storage, &msg)?;
+    assert_not_locked(deps.storage, &env, &msg)?;
+    if let ExecuteMsg::TransferNft { token_id, .. } | ExecuteMsg::SendNft { token_id, .. } = &msg {
+        on_transfer(deps.storage, token_id);
+    }
     match msg {
         ExecuteMsg::Receive(msg) => receive(deps, env, info, msg),
-        ExecuteMsg::WithdrawSales { amount } => withdraw_sales(deps, amount),
+        ExecuteMsg::ReceiveNft(msg) => receive_nft(deps, env, info, msg),
+        ExecuteMsg::Mint(mint_msg) => execute_mint(deps, env, info, mint_msg),
+        ExecuteMsg::WithdrawSales { amount, payment_token } => withdraw_sales(deps, info, amount, payment_token),
+        ExecuteMsg::SetRefundConfig { refund_config } => set_refund_config(deps, info, refund_config),
+        ExecuteMsg::WithdrawRefundReserve { amount, payment_token } => {
+            withdraw_refund_reserve(deps, info, amount, payment_token)
+        }
         ExecuteMsg::AddWhitelistAddresses { addresses } => add_whitelist_addresses(deps, info, addresses),
         ExecuteMsg::ToggleWhitelist { whitelist } => toggle_whitelist(deps, info, whitelist),
-        ExecuteMsg::SetWhitelistAdmin { whitelist_admin } => set_whitelist_admin(deps, info, whitelist_admin),
-        ExecuteMsg::SetAdmin { admin } => set_admin(deps, info, admin),
+        ExecuteMsg::ProposeWhitelistAdmin { new_admin, expiry } => {
+            propose_admin(deps, env, info, WHITELIST_ADMIN, new_admin, expiry)
+        }
+        ExecuteMsg::AcceptWhitelistAdmin {} => accept_whitelist_admin(deps, env, info),
+        ExecuteMsg::CancelWhitelistAdminProposal {} => cancel_admin_proposal(deps, info, WHITELIST_ADMIN),
+        ExecuteMsg::ProposeAdmin { new_admin, expiry } => {
+            propose_admin(deps, env, info, ADMIN, new_admin, expiry)
+        }
+        ExecuteMsg::AcceptAdmin {} => accept_admin(deps, env, info),
+        ExecuteMsg::CancelAdminProposal {} => cancel_admin_proposal(deps, info, ADMIN),
+        ExecuteMsg::GrantRole { address, role } => execute_grant_role(deps, info, address, role),
+        ExecuteMsg::RevokeRole { address, role } => execute_revoke_role(deps, info, address, role),
+        ExecuteMsg::UpdateConfig(update) => update_config(deps, info, update),
+        ExecuteMsg::SetPauseFlags {
+            buying,
+            metadata_updates,
+            transfers,
+        } => set_pause_flags(deps, info, buying, metadata_updates, transfers),
         ExecuteMsg::UpdateAllMetadata {
             token_id,
             extension,
         } => execute_update_all_metadata(deps, env, info, token_id, extension),
+        ExecuteMsg::SetSoulbound { token_id, soulbound } => set_soulbound(deps, info, token_id, soulbound),
+        ExecuteMsg::RecoverToken { token_id, recipient } => recover_token(deps, env, info, token_id, recipient),
+        ExecuteMsg::AllowLocker { locker } => allow_locker(deps, info, locker),
+        ExecuteMsg::RevokeLocker { locker } => revoke_locker(deps, info, locker),
+        ExecuteMsg::Lock { token_id, until } => lock_token(deps, env, info, token_id, until),
+        ExecuteMsg::Unlock { token_id } => unlock_token(deps, info, token_id),
+        ExecuteMsg::SetUser {
+            token_id,
+            user,
+            expires,
+        } => rental::set_user(deps, env, info, token_id, user, expires),
+        ExecuteMsg::ListRental {
+            token_id,
+            price,
+            duration,
+        } => rental::list_rental(deps, info, token_id, price, duration),
+        ExecuteMsg::CancelRental { token_id } => rental::cancel_rental(deps, info, token_id),
+        ExecuteMsg::Reindex { start_after, limit } => traits::reindex(deps, info, start_after, limit),
+        ExecuteMsg::UpdateSvgStyle { svg_style } => update_svg_style(deps, info, svg_style),
+        ExecuteMsg::AwardXp { token_id, amount } => progression::award_xp(deps, info, token_id, amount),
+        ExecuteMsg::SetLevelConfig { level_config } => progression::set_level_config(deps, info, level_config),
+        ExecuteMsg::SetItemCollection { collection, allowed } => {
+            equipment::set_item_collection(deps, info, collection, allowed)
+        }
+        ExecuteMsg::Unequip { token_id, slot } => equipment::unequip(deps, env, info, token_id, slot),
+        ExecuteMsg::Fuse { token_ids } => {
+            let owner = info.sender.to_string();
+            fusion::fuse(deps, env, owner, token_ids, None)
+        }
+        ExecuteMsg::SetFusionConfig { fusion_config } => fusion::set_fusion_config(deps, info, fusion_config),
+        ExecuteMsg::ListToken {
+            token_id,
+            price,
+            expires,
+        } => market::list_token(deps, env, info, token_id, price, expires),
+        ExecuteMsg::CancelListing { token_id } => market::cancel_listing(deps, info, token_id),
+        ExecuteMsg::CancelOffer { offer_id } => market::cancel_offer(deps, info, offer_id),
+        ExecuteMsg::AcceptOffer { offer_id, token_id } => market::accept_offer(deps, env, info, offer_id, token_id),
+        ExecuteMsg::SetMarketConfig { market_config } => market::set_market_config(deps, info, market_config),
+        ExecuteMsg::StartAuction {
+            lot,
+            reserve_price,
+            min_increment,
+            end_time,
+            time_extension,
+        } => auction::start_auction(deps, env, info, lot, reserve_price, min_increment, end_time, time_extension),
+        ExecuteMsg::CancelAuction { token_id } => auction::cancel_auction(deps, info, token_id),
+        ExecuteMsg::SettleAuction { token_id } => auction::settle_auction(deps, env, token_id),
         //ExecuteMsg::UpdateName { token_id, name } => execute_update_name(deps, info, token_id, name),
         // CW721 methods
         _ => LootopiaNFTContract::default()
@@ -75,6 +188,354 @@ pub fn execute(
             .map_err(|err| err.into()),
     }
 }
+// buying is checked in execute_buy, as it arrives wrapped in a cw20 Receive
+fn assert_not_paused(storage: &dyn Storage, msg: &ExecuteMsg) -> Result<(), ContractError> {
+    let flags = PAUSE_FLAGS.may_load(storage)?.unwrap_or_default();
+    let operation = match msg {
+        ExecuteMsg::TransferNft { .. }
+        | ExecuteMsg::SendNft { .. }
+        | ExecuteMsg::Approve { .. }
+        | ExecuteMsg::ApproveAll { .. }
+            if flags.transfers =>
+        {
+            "transfers"
+        }
+        ExecuteMsg::UpdateAllMetadata { .. } | ExecuteMsg::AwardXp { .. } if flags.metadata_updates => {
+            "metadata_updates"
+        }
+        _ => return Ok(()),
+    };
+    Err(ContractError::Paused {
+        operation: operation.to_string(),
+    })
+}
+
+pub(crate) fn is_soulbound(storage: &dyn Storage, token_id: &str) -> StdResult<bool> {
+    match SOULBOUND_TOKENS.may_load(storage, token_id)? {
+        Some(soulbound) => Ok(soulbound),
+        None => Ok(CONFIG.load(storage)?.soulbound),
+    }
+}
+
+fn assert_transferable(storage: &dyn Storage, msg: &ExecuteMsg) -> Result<(), ContractError> {
+    let token_id = match msg {
+        ExecuteMsg::TransferNft { token_id, .. }
+        | ExecuteMsg::SendNft { token_id, .. }
+        | ExecuteMsg::Approve { token_id, .. } => token_id,
+        // operators can't move soulbound tokens anyway, only reject in soulbound mode
+        ExecuteMsg::ApproveAll { .. } if CONFIG.load(storage)?.soulbound => {
+            return Err(ContractError::Soulbound {
+                token_id: "*".to_string(),
+            })
+        }
+        _ => return Ok(()),
+    };
+    if is_soulbound(storage, token_id)? {
+        return Err(ContractError::Soulbound {
+            token_id: token_id.clone(),
+        });
+    }
+    Ok(())
+}
+
+// the active lock of a token, expired locks are ignored
+pub(crate) fn active_lock(storage: &dyn Storage, env: &Env, token_id: &str) -> StdResult<Option<TokenLock>> {
+    Ok(LOCKS
+        .may_load(storage, token_id)?
+        .filter(|lock| !lock.until.is_expired(&env.block)))
+}
+
+fn assert_not_locked(storage: &dyn Storage, env: &Env, msg: &ExecuteMsg) -> Result<(), ContractError> {
+    let token_id = match msg {
+        ExecuteMsg::TransferNft { token_id, .. } | ExecuteMsg::SendNft { token_id, .. } => token_id,
+        _ => return Ok(()),
+    };
+    if active_lock(storage, env, token_id)?.is_some() {
+        return Err(ContractError::TokenLocked {
+            token_id: token_id.clone(),
+        });
+    }
+    Ok(())
+}
+
+/// `Timestamp::plus_seconds` overflows silently, durations set by users go through this
+pub(crate) fn checked_plus_seconds(time: Timestamp, seconds: u64) -> StdResult<Timestamp> {
+    let nanos = Uint64::from(seconds)
+        .checked_mul(Uint64::from(1_000_000_000u64))?
+        .checked_add(Uint64::from(time.nanos()))?;
+    Ok(Timestamp::from_nanos(nanos.u64()))
+}
+
+fn assert_buying_allowed(storage: &dyn Storage) -> Result<(), ContractError> {
+    if PAUSE_FLAGS.may_load(storage)?.unwrap_or_default().buying {
+        return Err(ContractError::Paused {
+            operation: "buying".to_string(),
+        });
+    }
+    Ok(())
+}
+
+pub(crate) fn assert_transfers_allowed(storage: &dyn Storage) -> Result<(), ContractError> {
+    if PAUSE_FLAGS.may_load(storage)?.unwrap_or_default().transfers {
+        return Err(ContractError::Paused {
+            operation: "transfers".to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// The checks a transfer goes through, for tokens the contract moves or
+/// burns on behalf of their owner
+pub(crate) fn assert_movable(storage: &dyn Storage, env: &Env, token_id: &str) -> Result<(), ContractError> {
+    assert_transfers_allowed(storage)?;
+    if is_soulbound(storage, token_id)? {
+        return Err(ContractError::Soulbound {
+            token_id: token_id.to_string(),
+        });
+    }
+    if active_lock(storage, env, token_id)?.is_some() {
+        return Err(ContractError::TokenLocked {
+            token_id: token_id.to_string(),
+        });
+    }
+    Ok(())
+}
+
+// state tied to the current owner, dropped whenever the token changes hands
+fn on_transfer(storage: &mut dyn Storage, token_id: &str) {
+    LISTINGS.remove(storage, token_id);
+    USERS.remove(storage, token_id);
+    RENTAL_LISTINGS.remove(storage, token_id);
+}
+
+/// Moves a token on behalf of the contract, bypassing approvals
+pub(crate) fn move_token(storage: &mut dyn Storage, token_id: &str, recipient: &Addr) -> Result<(), ContractError> {
+    LootopiaNFTContract::default()
+        .tokens
+        .update(storage, token_id, |token| match token {
+            Some(mut token_info) => {
+                token_info.owner = recipient.clone();
+                token_info.approvals = vec![];
+                Ok(token_info)
+            }
+            None => Err(ContractError::TokenNotFound {}),
+        })?;
+    on_transfer(storage, token_id);
+    Ok(())
+}
+
+fn allow_locker(deps: DepsMut, info: MessageInfo, locker: String) -> Result<Response, ContractError> {
+    let locker = deps.api.addr_validate(&locker)?;
+    LOCKERS.save(deps.storage, (info.sender.as_bytes(), locker.as_bytes()), &true)?;
+    Ok(Response::new()
+        .add_attribute("action", "allow_locker")
+        .add_event(lock_event("allow_locker", info.sender.as_str(), locker.as_str())))
+}
+
+// existing locks stay until the game unlocks them or they expire
+fn revoke_locker(deps: DepsMut, info: MessageInfo, locker: String) -> Result<Response, ContractError> {
+    let locker = deps.api.addr_validate(&locker)?;
+    LOCKERS.remove(deps.storage, (info.sender.as_bytes(), locker.as_bytes()));
+    Ok(Response::new()
+        .add_attribute("action", "revoke_locker")
+        .add_event(lock_event("revoke_locker", info.sender.as_str(), locker.as_str())))
+}
+
+fn lock_token(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    token_id: String,
+    until: Expiration,
+) -> Result<Response, ContractError> {
+    let token = LootopiaNFTContract::default().tokens.load(deps.storage, &token_id)?;
+    let allowed = LOCKERS
+        .may_load(deps.storage, (token.owner.as_bytes(), info.sender.as_bytes()))?
+        .unwrap_or(false);
+    if !allowed {
+        return Err(ContractError::Unauthorized {});
+    }
+    if until.is_expired(&env.block) {
+        return Err(ContractError::Expired {});
+    }
+    if let Some(lock) = active_lock(deps.storage, &env, &token_id)? {
+        // only the current locker can extend its lock
+        if lock.locker != info.sender {
+            return Err(ContractError::TokenLocked { token_id });
+        }
+    }
+    LOCKS.save(
+        deps.storage,
+        &token_id,
+        &TokenLock {
+            locker: info.sender.clone(),
+            until,
+        },
+    )?;
+    Ok(Response::new()
+        .add_attribute("action", "lock")
+        .add_attribute("token_id", &token_id)
+        .add_event(
+            lock_event("lock", token.owner.as_str(), info.sender.as_str())
+                .add_attribute(events::TOKEN_ID, token_id)
+                .add_attribute("until", until.to_string()),
+        ))
+}
+
+fn unlock_token(deps: DepsMut, info: MessageInfo, token_id: String) -> Result<Response, ContractError> {
+    let lock = LOCKS
+        .may_load(deps.storage, &token_id)?
+        .ok_or(ContractError::Unauthorized {})?;
+    if lock.locker != info.sender {
+        return Err(ContractError::Unauthorized {});
+    }
+    LOCKS.remove(deps.storage, &token_id);
+    let token = LootopiaNFTContract::default().tokens.load(deps.storage, &token_id)?;
+    Ok(Response::new()
+        .add_attribute("action", "unlock")
+        .add_attribute("token_id", &token_id)
+        .add_event(
+            lock_event("unlock", token.owner.as_str(), info.sender.as_str())
+                .add_attribute(events::TOKEN_ID, token_id),
+        ))
+}
+
+fn set_soulbound(
+    deps: DepsMut,
+    info: MessageInfo,
+    token_id: String,
+    soulbound: Option<bool>,
+) -> Result<Response, ContractError> {
+    assert_role(deps.storage, &info.sender, Role::Admin)?;
+    if LootopiaNFTContract::default().tokens.may_load(deps.storage, &token_id)?.is_none() {
+        return Err(ContractError::TokenNotFound {});
+    }
+    match soulbound {
+        Some(soulbound) => SOULBOUND_TOKENS.save(deps.storage, &token_id, &soulbound)?,
+        None => SOULBOUND_TOKENS.remove(deps.storage, &token_id),
+    }
+    Ok(Response::new()
+        .add_attribute("action", "set_soulbound")
+        .add_event(
+            soulbound_event("set_flag", info.sender.as_str(), &token_id)
+                .add_attribute("soulbound", soulbound.map_or("config".to_string(), |s| s.to_string())),
+        ))
+}
+
+// moves a token to a new wallet of its owner, bypassing soulbound and approvals
+// but not the transfers pause
+fn recover_token(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    token_id: String,
+    recipient: String,
+) -> Result<Response, ContractError> {
+    assert_role(deps.storage, &info.sender, Role::Admin)?;
+    // the contract holds tokens under auction, settling hands them out
+    if AUCTIONS.may_load(deps.storage, &token_id)?.is_some() {
+        return Err(ContractError::TokenLocked { token_id });
+    }
+    assert_transfers_allowed(deps.storage)?;
+    // moving it would drop the renter, who paid for the time left
+    rental::assert_not_rented(deps.as_ref(), &env, &token_id)?;
+    let recipient = deps.api.addr_validate(&recipient)?;
+    move_token(deps.storage, &token_id, &recipient)?;
+    Ok(Response::new()
+        .add_attribute("action", "recover_token")
+        .add_attribute("token_id", &token_id)
+        .add_event(
+            soulbound_event("recover", info.sender.as_str(), &token_id)
+                .add_attribute(events::RECIPIENT, recipient),
+        ))
+}
+
+fn update_config(deps: DepsMut, info: MessageInfo, update: UpdateConfigMsg) -> Result<Response, ContractError> {
+    assert_role(deps.storage, &info.sender, Role::Admin)?;
+    let mut config = CONFIG.load(deps.storage)?;
+    let mut event = config_event(info.sender.as_str());
+
+    if let Some(payment_token) = update.payment_token {
+        let payment_token = deps.api.addr_validate(&payment_token)?.to_string();
+        event = config_change(event, "payment_token", &config.payment_token, &payment_token);
+        config.payment_token = payment_token;
+    }
+    if let Some(price) = update.price {
+        event = config_change(event, "price", config.price, price);
+        config.price = price;
+    }
+    if let Some(treasury) = update.treasury {
+        let treasury = deps.api.addr_validate(&treasury)?.to_string();
+        event = config_change(event, "treasury", &config.treasury, &treasury);
+        config.treasury = treasury;
+    }
+    if let Some(limit_per_address) = update.limit_per_address {
+        event = config_change(event, "limit_per_address", config.limit_per_address, limit_per_address);
+        config.limit_per_address = limit_per_address;
+    }
+    if let Some(free_mint) = update.free_mint {
+        event = config_change(event, "free_mint", config.free_mint, free_mint);
+        config.free_mint = free_mint;
+    }
+    if let Some(sale_start) = update.sale_start {
+        event = config_change(
+            event,
+            "sale_start",
+            config.sale_start.map_or("none".to_string(), |start| start.to_string()),
+            sale_start,
+        );
+        config.sale_start = Some(sale_start);
+    }
+    if let Some(soulbound) = update.soulbound {
+        event = config_change(event, "soulbound", config.soulbound, soulbound);
+        config.soulbound = soulbound;
+    }
+    if let Some(escrow) = update.escrow {
+        event = config_change(event, "escrow", config.escrow, escrow);
+        config.escrow = escrow;
+    }
+
+    // sanity checks on the resulting config
+    if config.price.is_zero() && !config.free_mint {
+        return Err(ContractError::InvalidConfig {
+            reason: "price must be non-zero unless free mint is enabled".to_string(),
+        });
+    }
+    if config.limit_per_address < 1 {
+        return Err(ContractError::InvalidConfig {
+            reason: "limit_per_address must be at least 1".to_string(),
+        });
+    }
+
+    CONFIG.save(deps.storage, &config)?;
+    Ok(Response::new()
+        .add_attribute("action", "update_config")
+        .add_event(event))
+}
+
+fn set_pause_flags(
+    deps: DepsMut,
+    info: MessageInfo,
+    buying: Option<bool>,
+    metadata_updates: Option<bool>,
+    transfers: Option<bool>,
+) -> Result<Response, ContractError> {
+    assert_role(deps.storage, &info.sender, Role::Pauser)?;
+    let mut flags = PAUSE_FLAGS.may_load(deps.storage)?.unwrap_or_default();
+    flags.buying = buying.unwrap_or(flags.buying);
+    flags.metadata_updates = metadata_updates.unwrap_or(flags.metadata_updates);
+    flags.transfers = transfers.unwrap_or(flags.transfers);
+    PAUSE_FLAGS.save(deps.storage, &flags)?;
+    Ok(Response::new()
+        .add_attribute("action", "set_pause_flags")
+        .add_event(
+            pause_event(info.sender.as_str())
+                .add_attribute("buying", flags.buying.to_string())
+                .add_attribute("metadata_updates", flags.metadata_updates.to_string())
+                .add_attribute("transfers", flags.transfers.to_string()),
+        ))
+}
+
 /*
 pub fn execute_update_name(
     deps: DepsMut,
@@ -94,86 +555,197 @@ pub fn execute_update_all_metadata(
     extension: Extension,
 ) -> Result<Response, ContractError> {
     let cw721_contract = LootopiaNFTContract::default();
-    let config = CONFIG.load(deps.storage)?; 
-    if info.sender != config.admin {
-        return Err(ContractError::Unauthorized {});
-    }
-    cw721_contract
+    assert_role(deps.storage, &info.sender, Role::MetadataEditor)?;
+    let mut token_info = cw721_contract
         .tokens
-        .update(deps.storage, &token_id, |token| match token {
-            Some(mut token_info) => {
-                token_info.extension = extension;
-                Ok(token_info)
-            }
-            None => return Err(ContractError::TokenNotFound {}),
-        })?;
+        .may_load(deps.storage, &token_id)?
+        .ok_or(ContractError::TokenNotFound {})?;
+    unindex_traits(deps.storage, &token_id, &token_info.extension)?;
+    index_traits(deps.storage, &token_id, &extension)?;
+    token_info.extension = extension;
+    cw721_contract.tokens.save(deps.storage, &token_id, &token_info)?;
 
     Ok(Response::new()
         .add_attribute("action", "update")
-        .add_attribute("token_id", token_id))
+        .add_attribute("token_id", &token_id)
+        .add_event(metadata_event("update", info.sender.as_str()).add_attribute(events::TOKEN_ID, token_id)))
 }
 
-fn set_admin(deps: DepsMut, info: MessageInfo, admin: String) -> Result<Response, ContractError> {
-    let mut config = CONFIG.load(deps.storage)?;
-    if info.sender != config.admin {
-        return Err(ContractError::Unauthorized {});
+fn update_svg_style(deps: DepsMut, info: MessageInfo, svg_style: SvgStyle) -> Result<Response, ContractError> {
+    assert_role(deps.storage, &info.sender, Role::MetadataEditor)?;
+    validate_style(&svg_style)?;
+    SVG_STYLE.save(deps.storage, &svg_style)?;
+    Ok(Response::new()
+        .add_attribute("action", "update_svg_style")
+        .add_event(metadata_event("update_svg_style", info.sender.as_str())))
+}
+
+// names of the admin roles handed over in two steps, as used in events
+const ADMIN: &str = "admin";
+const WHITELIST_ADMIN: &str = "whitelist_admin";
+
+fn admin_proposal(role: &str) -> Item<'static, AdminProposal> {
+    if role == WHITELIST_ADMIN {
+        WHITELIST_ADMIN_PROPOSAL
+    } else {
+        ADMIN_PROPOSAL
     }
-    config.admin = admin;
-    CONFIG.save(deps.storage, &config)?;
-    Ok(Response::new())
 }
 
-fn set_whitelist_admin(deps: DepsMut, info: MessageInfo, whitelist_admin: String) -> Result<Response, ContractError> {
-    let mut config = CONFIG.load(deps.storage)?;
+// both admin roles are handed over by the admin, and only become effective
+// once the proposed address accepts them
+fn propose_admin(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    role: &str,
+    new_admin: String,
+    expiry: Option<Expiration>,
+) -> Result<Response, ContractError> {
+    assert_role(deps.storage, &info.sender, Role::Admin)?;
+
+    let expiry = expiry.unwrap_or_default();
+    if expiry.is_expired(&env.block) {
+        return Err(ContractError::Expired {});
+    }
+    let new_admin = deps.api.addr_validate(&new_admin)?;
+    admin_proposal(role).save(
+        deps.storage,
+        &AdminProposal {
+            new_admin: new_admin.clone(),
+            expiry,
+        },
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "propose_admin")
+        .add_attribute("new_admin", &new_admin)
+        .add_event(admin_event("propose", role, Some(new_admin.as_str()))))
+}
 
-    // check if person has admin rights
-    if info.sender != config.admin {
+// loads the proposal and checks it can be accepted by the sender
+fn take_admin_proposal(
+    storage: &mut dyn Storage,
+    env: &Env,
+    info: &MessageInfo,
+    role: &str,
+) -> Result<AdminProposal, ContractError> {
+    let proposal = admin_proposal(role);
+    let pending = proposal
+        .may_load(storage)?
+        .ok_or(ContractError::NoPendingProposal {})?;
+    if info.sender != pending.new_admin {
         return Err(ContractError::Unauthorized {});
     }
+    if pending.expiry.is_expired(&env.block) {
+        return Err(ContractError::ProposalExpired {});
+    }
+    proposal.remove(storage);
+    Ok(pending)
+}
 
-    config.whitelist_admin = whitelist_admin;
+fn accept_admin(deps: DepsMut, env: Env, info: MessageInfo) -> Result<Response, ContractError> {
+    let pending = take_admin_proposal(deps.storage, &env, &info, ADMIN)?;
+    let mut config = CONFIG.load(deps.storage)?;
+    revoke_role(deps.storage, &Addr::unchecked(&config.admin), Role::Admin)?;
+    grant_role(deps.storage, &pending.new_admin, Role::Admin)?;
+    config.admin = pending.new_admin.to_string();
     CONFIG.save(deps.storage, &config)?;
-    Ok(Response::new())
+    Ok(Response::new()
+        .add_attribute("action", "accept_admin")
+        .add_attribute("admin", &pending.new_admin)
+        .add_event(admin_event("accept", ADMIN, Some(pending.new_admin.as_str()))))
 }
 
-fn toggle_whitelist(deps: DepsMut, info: MessageInfo, whitelist: bool) -> Result<Response, ContractError> {
+fn accept_whitelist_admin(deps: DepsMut, env: Env, info: MessageInfo) -> Result<Response, ContractError> {
+    let pending = take_admin_proposal(deps.storage, &env, &info, WHITELIST_ADMIN)?;
     let mut config = CONFIG.load(deps.storage)?;
+    revoke_role(deps.storage, &Addr::unchecked(&config.whitelist_admin), Role::WhitelistManager)?;
+    grant_role(deps.storage, &pending.new_admin, Role::WhitelistManager)?;
+    config.whitelist_admin = pending.new_admin.to_string();
+    CONFIG.save(deps.storage, &config)?;
+    Ok(Response::new()
+        .add_attribute("action", "accept_whitelist_admin")
+        .add_attribute("whitelist_admin", &pending.new_admin)
+        .add_event(admin_event("accept", WHITELIST_ADMIN, Some(pending.new_admin.as_str()))))
+}
+
+fn cancel_admin_proposal(
+    deps: DepsMut,
+    info: MessageInfo,
+    role: &str,
+) -> Result<Response, ContractError> {
+    assert_role(deps.storage, &info.sender, Role::Admin)?;
+    let proposal = admin_proposal(role);
+    if proposal.may_load(deps.storage)?.is_none() {
+        return Err(ContractError::NoPendingProposal {});
+    }
+    proposal.remove(deps.storage);
+    Ok(Response::new()
+        .add_attribute("action", "cancel_admin_proposal")
+        .add_event(admin_event("cancel", role, None)))
+}
 
-    // check if person has admin rights
-    let whitelist_admin = config.whitelist_admin.clone();
-    if info.sender != whitelist_admin || info.sender != config.admin {
+fn execute_grant_role(deps: DepsMut, info: MessageInfo, address: String, role: Role) -> Result<Response, ContractError> {
+    assert_role(deps.storage, &info.sender, Role::Admin)?;
+    let address = deps.api.addr_validate(&address)?;
+    grant_role(deps.storage, &address, role)?;
+    Ok(Response::new()
+        .add_attribute("action", "grant_role")
+        .add_attribute("address", &address)
+        .add_event(role_event("grant", address.as_str(), role)))
+}
+
+fn execute_revoke_role(deps: DepsMut, info: MessageInfo, address: String, role: Role) -> Result<Response, ContractError> {
+    assert_role(deps.storage, &info.sender, Role::Admin)?;
+    let address = deps.api.addr_validate(&address)?;
+    // the admin in config can only be replaced through a handover
+    let config = CONFIG.load(deps.storage)?;
+    if role == Role::Admin && address == config.admin {
         return Err(ContractError::Unauthorized {});
     }
+    revoke_role(deps.storage, &address, role)?;
+    Ok(Response::new()
+        .add_attribute("action", "revoke_role")
+        .add_attribute("address", &address)
+        .add_event(role_event("revoke", address.as_str(), role)))
+}
+
+fn toggle_whitelist(deps: DepsMut, info: MessageInfo, whitelist: bool) -> Result<Response, ContractError> {
+    assert_role(deps.storage, &info.sender, Role::WhitelistManager)?;
+    let mut config = CONFIG.load(deps.storage)?;
     config.whitelist = whitelist;
     CONFIG.save(deps.storage, &config)?;
-    Ok(Response::new())
+    Ok(Response::new()
+        .add_attribute("action", "toggle_whitelist")
+        .add_event(whitelist_event("toggle").add_attribute("whitelist", whitelist.to_string())))
 }
 
 fn add_whitelist_addresses(deps: DepsMut, info: MessageInfo, addresses: Vec<String>) -> Result<Response, ContractError> {
-    // check if the person executing this is the whitelist admin
-    let config = CONFIG.load(deps.storage)?;
-    let whitelist_admin = config.whitelist_admin.clone();
-    if info.sender != whitelist_admin || info.sender != config.admin {
-        return Err(ContractError::Unauthorized {});
-    }
+    // check if the person executing this is a whitelist manager
+    assert_role(deps.storage, &info.sender, Role::WhitelistManager)?;
 
     // iterate addresses and add to whitelist
+    let mut event = whitelist_event("add_addresses");
     for address in addresses {
         let mut by_address = WHITELIST_BY_ADDRESS
-        .load(deps.storage, &address.as_bytes())
-        .unwrap_or(vec![]);
+        .load(deps.storage, address.as_bytes())
+        .unwrap_or_default();
     
     
-        if by_address.len() < 1 as usize {
+        if by_address.is_empty() {
             by_address.push(1);
         }
         WHITELIST_BY_ADDRESS.save(deps.storage, address.as_bytes(), &by_address)?;
+        event = event.add_attribute(events::ADDRESS, address);
     }
     
-    Ok(Response::new())
+    Ok(Response::new()
+        .add_attribute("action", "add_whitelist_addresses")
+        .add_event(event))
 }
 
-fn get_hash<T: Hash>(seed: &T) -> u64 {
+pub(crate) fn get_hash<T: Hash>(seed: &T) -> u64 {
     let mut hasher = DefaultHasher::new();
     seed.hash(&mut hasher);
     hasher.finish()
@@ -185,7 +757,11 @@ fn receive(
     info: MessageInfo,
     cw20_msg: Cw20ReceiveMsg,
 ) -> Result<Response, ContractError> {
-    match from_binary(&cw20_msg.msg)? {
+    let msg: ReceiveMsg = from_binary(&cw20_msg.msg)?;
+    if !matches!(msg, ReceiveMsg::FundRefundReserve {}) {
+        assert_buying_allowed(deps.storage)?;
+    }
+    match msg {
         ReceiveMsg::Buy {
             mint_msg,
         } => execute_buy(
@@ -195,74 +771,472 @@ fn receive(
             info.sender.to_string().clone(), // the token sent
             cw20_msg.amount, // the amount sent
             cw20_msg.sender, // address of the buyer
-            mint_msg
+            *mint_msg
+        ),
+        ReceiveMsg::Fuse { token_ids } => fusion::fuse(
+            deps,
+            env,
+            cw20_msg.sender, // the owner of the characters
+            token_ids,
+            Some(TokenBalance {
+                token: info.sender.to_string(),
+                amount: cw20_msg.amount,
+            }),
+        ),
+        ReceiveMsg::BuyListing { token_id } => market::buy_listing(
+            deps,
+            env,
+            cw20_msg.sender, // the buyer
+            token_id,
+            TokenBalance {
+                token: info.sender.to_string(),
+                amount: cw20_msg.amount,
+            },
+        ),
+        ReceiveMsg::MakeOffer { target, expires } => market::make_offer(
+            deps,
+            env,
+            cw20_msg.sender, // the bidder
+            target,
+            expires,
+            TokenBalance {
+                token: info.sender.to_string(),
+                amount: cw20_msg.amount,
+            },
+        ),
+        ReceiveMsg::Rent { token_id } => rental::rent(
+            deps,
+            env,
+            cw20_msg.sender, // the renter
+            token_id,
+            TokenBalance {
+                token: info.sender.to_string(),
+                amount: cw20_msg.amount,
+            },
+        ),
+        ReceiveMsg::Bid { token_id } => auction::bid(
+            deps,
+            env,
+            cw20_msg.sender, // the bidder
+            token_id,
+            TokenBalance {
+                token: info.sender.to_string(),
+                amount: cw20_msg.amount,
+            },
         ),
-        _ => Err(ContractError::Unauthorized {}),
+        ReceiveMsg::FundRefundReserve {} => {
+            add_to_ledger(deps.storage, REFUND_RESERVE, info.sender.as_str(), cw20_msg.amount)?;
+            Ok(Response::new()
+                .add_attribute("action", "fund_refund_reserve")
+                .add_attribute("amount", cw20_msg.amount))
+        }
     }
 }
 
-fn withdraw_sales(deps: DepsMut, amount: Uint128) -> Result<Response, ContractError> {
-    let cfg = CONFIG.load(deps.storage)?;
-
-    let to_withdraw = Asset {
-        info: AssetInfo::Token {
-                contract_addr: cfg.payment_token.clone(),
-        },
-        amount: amount.clone(),
-    };
-    let treasury = deps.api.addr_validate(&cfg.treasury)?;
-    Ok(Response::new().add_message(to_withdraw.into_msg(&deps.querier, treasury)?))
-}
-
-fn execute_buy(
-    deps: DepsMut, 
+fn receive_nft(
+    deps: DepsMut,
     env: Env,
-    mut info: MessageInfo,
-    token_sent: String, 
-    amount_sent: Uint128, 
-    buyer: String,
-    mut mint_msg: MintMsg,
+    info: MessageInfo,
+    cw721_msg: Cw721ReceiveMsg,
 ) -> Result<Response, ContractError> {
+    match from_binary(&cw721_msg.msg)? {
+        NftReceiveMsg::Refund {} => execute_refund(
+            deps,
+            env,
+            info,
+            cw721_msg.sender, // the buyer returning the token
+            cw721_msg.token_id,
+        ),
+        NftReceiveMsg::Equip { token_id, slot } => equipment::equip(
+            deps,
+            env,
+            info,
+            cw721_msg.sender, // the owner of the item and the character
+            cw721_msg.token_id,
+            token_id,
+            slot,
+        ),
+    }
+}
+
+fn add_to_ledger(storage: &mut dyn Storage, ledger: Map<&[u8], Uint128>, token: &str, amount: Uint128) -> StdResult<()> {
+    ledger.update(storage, token.as_bytes(), |balance| -> StdResult<_> {
+        Ok(balance.unwrap_or_default().checked_add(amount)?)
+    })?;
+    Ok(())
+}
 
+// takes amount out of a ledger, failing with `insufficient(available, requested)`
+fn debit_ledger(
+    storage: &mut dyn Storage,
+    ledger: Map<&[u8], Uint128>,
+    token: &str,
+    amount: Uint128,
+    insufficient: fn(Uint128, Uint128) -> ContractError,
+) -> Result<(), ContractError> {
+    let available = ledger.may_load(storage, token.as_bytes())?.unwrap_or_default();
+    if amount > available {
+        return Err(insufficient(available, amount));
+    }
+    ledger.save(storage, token.as_bytes(), &(available - amount))?;
+    Ok(())
+}
 
+// removes a token held by anyone, cw721-base has no burn in this version
+pub(crate) fn burn_token(storage: &mut dyn Storage, token_id: &str) -> Result<(), ContractError> {
+    equipment::assert_unequipped(storage, token_id)?;
     let cw721_contract = LootopiaNFTContract::default();
-    // verify token_sent == payment_token
-    let config = CONFIG.load(deps.storage)?;
-    let admin = config.admin.clone(); 
-    if config.payment_token != token_sent && &buyer != &admin {
+    let token = cw721_contract.tokens.load(storage, token_id)?;
+    unindex_traits(storage, token_id, &token.extension)?;
+    on_transfer(storage, token_id);
+    cw721_contract.tokens.remove(storage, token_id)?;
+    SOULBOUND_TOKENS.remove(storage, token_id);
+    LOCKS.remove(storage, token_id);
+    PROGRESSION.remove(storage, token_id);
+    PURCHASES.remove(storage, token_id);
+    let count = cw721_contract.token_count(storage)?;
+    cw721_contract.token_count.save(storage, &count.saturating_sub(1))?;
+    Ok(())
+}
+
+fn set_refund_config(
+    deps: DepsMut,
+    info: MessageInfo,
+    refund_config: Option<RefundConfig>,
+) -> Result<Response, ContractError> {
+    assert_role(deps.storage, &info.sender, Role::Admin)?;
+    let mut event = config_event(info.sender.as_str());
+    match refund_config {
+        Some(refund_config) => {
+            // a zero rate would burn the returned token for nothing
+            if refund_config.refund_rate.is_zero() || refund_config.refund_rate > Decimal::one() {
+                return Err(ContractError::InvalidConfig {
+                    reason: "refund_rate must be above 0 and at most 1".to_string(),
+                });
+            }
+            event = event
+                .add_attribute("refund_window_blocks", refund_config.window_blocks.to_string())
+                .add_attribute("refund_rate", refund_config.refund_rate.to_string());
+            REFUND_CONFIG.save(deps.storage, &refund_config)?;
+        }
+        None => {
+            event = event.add_attribute("refunds", "disabled");
+            REFUND_CONFIG.remove(deps.storage);
+        }
+    }
+    Ok(Response::new()
+        .add_attribute("action", "set_refund_config")
+        .add_event(event))
+}
+
+// the token arrives through our own SendNft, so it is already owned by the
+// contract when this runs
+fn execute_refund(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    sender: String,
+    token_id: String,
+) -> Result<Response, ContractError> {
+    if info.sender != env.contract.address {
         return Err(ContractError::Unauthorized {});
-    } // verify amount_sent >= price
-    if amount_sent < config.price && &buyer != &admin {
+    }
+    let refund_config = REFUND_CONFIG
+        .may_load(deps.storage)?
+        .ok_or(ContractError::RefundsDisabled {})?;
+    // tokens minted outside of the sale were never bought
+    let purchase = PURCHASES
+        .may_load(deps.storage, &token_id)?
+        .ok_or(ContractError::RefundWindowClosed {})?;
+    if purchase.buyer != sender {
         return Err(ContractError::Unauthorized {});
     }
+    if env.block.height > purchase.height.saturating_add(refund_config.window_blocks) {
+        return Err(ContractError::RefundWindowClosed {});
+    }
 
-    let mut loot = LOOT.load(deps.storage)?;
-
-    // make sure total minted <= total num items
-    if loot.curr_num_items > loot.num_items && &buyer != &admin {
-        return Err(ContractError::MaxTokensMinted {});
+    let refund = purchase.price.amount * refund_config.refund_rate;
+    if refund.is_zero() {
+        return Err(ContractError::NothingToRefund {});
     }
+    debit_ledger(deps.storage, REFUND_RESERVE, &purchase.price.token, refund, |available, requested| {
+        ContractError::InsufficientRefundReserve { available, requested }
+    })?;
+    burn_token(deps.storage, &token_id)?;
+    // the slot goes back on sale, the next sale still takes a new id
+    LOOT.update(deps.storage, |mut loot| -> StdResult<_> {
+        loot.refunded += 1;
+        Ok(loot)
+    })?;
 
-    // check limit per address
+    // the refunded mint no longer counts towards the buyer's limit
     let mut by_address = MINTS_BY_ADDRESS
-    .load(deps.storage, &buyer.as_bytes())
-    .unwrap_or(vec![]);
-
-    if by_address.len() >= config.limit_per_address as usize && &buyer != &admin {
-        return Err(ContractError::MaxMintsPerAddress {});
+        .load(deps.storage, sender.as_bytes())
+        .unwrap_or_default();
+    // records hold the token count before the mint, the id is one more
+    if let Some(pos) = by_address.iter().position(|n| (n + 1).to_string() == token_id) {
+        by_address.remove(pos);
+        MINTS_BY_ADDRESS.save(deps.storage, sender.as_bytes(), &by_address)?;
     }
-    by_address.push(loot.curr_num_items);
+    unrecord_sale(deps.storage, &sender, &purchase.price.token, refund)?;
+
+    let to_refund = Asset {
+        info: AssetInfo::Token {
+                contract_addr: purchase.price.token.clone(),
+        },
+        amount: refund,
+    };
+    Ok(Response::new()
+        .add_attribute("action", "refund")
+        .add_attribute("token_id", &token_id)
+        .add_message(to_refund.into_msg(&deps.querier, purchase.buyer)?)
+        .add_event(refund_event(&sender, &token_id, &purchase.price.token, refund)))
+}
+
+// only what was recorded in a ledger can be withdrawn, to the treasury
+fn withdraw_ledger(
+    deps: DepsMut,
+    info: MessageInfo,
+    ledger: Map<&[u8], Uint128>,
+    ledger_name: &str,
+    amount: Uint128,
+    payment_token: Option<String>,
+    insufficient: fn(Uint128, Uint128) -> ContractError,
+) -> Result<Response, ContractError> {
+    assert_role(deps.storage, &info.sender, Role::Treasurer)?;
+    let cfg = CONFIG.load(deps.storage)?;
+    let payment_token = match payment_token {
+        Some(token) => deps.api.addr_validate(&token)?.to_string(),
+        None => cfg.payment_token.clone(),
+    };
+    debit_ledger(deps.storage, ledger, &payment_token, amount, insufficient)?;
+
+    let to_withdraw = Asset {
+        info: AssetInfo::Token {
+                contract_addr: payment_token.clone(),
+        },
+        amount,
+    };
+    let treasury = deps.api.addr_validate(&cfg.treasury)?;
+    Ok(Response::new()
+        .add_attribute("action", format!("withdraw_{}", ledger_name))
+        .add_message(to_withdraw.into_msg(&deps.querier, treasury.clone())?)
+        .add_event(withdraw_event(ledger_name, info.sender.as_str(), &payment_token, amount, treasury.as_str())))
+}
+
+// sales are recorded in escrow by execute_buy
+fn withdraw_sales(
+    deps: DepsMut,
+    info: MessageInfo,
+    amount: Uint128,
+    payment_token: Option<String>,
+) -> Result<Response, ContractError> {
+    withdraw_ledger(deps, info, SALES_BALANCE, "sales", amount, payment_token, |available, requested| {
+        ContractError::InsufficientSalesBalance { available, requested }
+    })
+}
+
+fn withdraw_refund_reserve(
+    deps: DepsMut,
+    info: MessageInfo,
+    amount: Uint128,
+    payment_token: Option<String>,
+) -> Result<Response, ContractError> {
+    withdraw_ledger(deps, info, REFUND_RESERVE, "refund_reserve", amount, payment_token, |available, requested| {
+        ContractError::InsufficientRefundReserve { available, requested }
+    })
+}
+
+// mints a token as is, outside of the sale
+fn execute_mint(deps: DepsMut, env: Env, info: MessageInfo, mint_msg: MintMsg) -> Result<Response, ContractError> {
+    assert_role(deps.storage, &info.sender, Role::Minter)?;
+    let (_, response) = mint_from_reserve(deps, env, mint_msg)?;
+    Ok(response)
+}
+
+/// Mints the next token out of the reserved allocation, returning its id
+pub(crate) fn mint_from_reserve(
+    deps: DepsMut,
+    env: Env,
+    mut mint_msg: MintMsg,
+) -> Result<(String, Response), ContractError> {
+    let mut loot = LOOT.load(deps.storage)?;
+    if loot.minted() >= loot.num_items {
+        return Err(ContractError::SoldOut {});
+    }
+    if loot.reserved_minted >= loot.num_reserved {
+        return Err(ContractError::ReservedAllocationExhausted {});
+    }
+    loot.curr_num_items += 1;
+    loot.reserved_minted += 1;
+    LOOT.save(deps.storage, &loot)?;
+
+    let cw721_contract = LootopiaNFTContract::default();
+    let info = MessageInfo {
+        sender: cw721_contract.minter.load(deps.storage)?,
+        funds: vec![],
+    };
+    let token_id = loot.curr_num_items.to_string();
+    mint_msg.token_id = token_id.clone();
+    index_traits(deps.storage, &mint_msg.token_id, &mint_msg.extension)?;
+    let response = cw721_contract.mint(deps, env, info, mint_msg)?;
+    Ok((token_id, response))
+}
+
+fn add_to_balances(balances: &mut Vec<TokenBalance>, token: &str, amount: Uint128) -> StdResult<()> {
+    match balances.iter_mut().find(|balance| balance.token == token) {
+        Some(balance) => balance.amount = balance.amount.checked_add(amount)?,
+        None => balances.push(TokenBalance {
+            token: token.to_string(),
+            amount,
+        }),
+    }
+    Ok(())
+}
+
+// keeps the sales ledger up to date, so treasury receipts can be reconciled
+pub(crate) fn record_sale(storage: &mut dyn Storage, buyer: &str, token: &str, amount: Uint128) -> StdResult<()> {
+    let mut buyer_stats = BUYER_STATS.may_load(storage, buyer.as_bytes())?;
+    let mut stats = SALES_STATS.may_load(storage)?.unwrap_or_default();
+    if buyer_stats.is_none() {
+        stats.unique_buyers += 1;
+    }
+    stats.num_sales += 1;
+    add_to_balances(&mut stats.revenue, token, amount)?;
+    SALES_STATS.save(storage, &stats)?;
+
+    let buyer_stats = buyer_stats.get_or_insert_with(BuyerStats::default);
+    buyer_stats.num_purchases += 1;
+    add_to_balances(&mut buyer_stats.spent, token, amount)?;
+    BUYER_STATS.save(storage, buyer.as_bytes(), buyer_stats)
+}
+
+fn subtract_from_balances(balances: &mut [TokenBalance], token: &str, amount: Uint128) {
+    if let Some(balance) = balances.iter_mut().find(|balance| balance.token == token) {
+        balance.amount = balance.amount.saturating_sub(amount);
+    }
+}
+
+// reverses record_sale for a refund, the part of the price that was kept
+// stays in the revenue
+fn unrecord_sale(storage: &mut dyn Storage, buyer: &str, token: &str, refund: Uint128) -> StdResult<()> {
+    let mut stats = SALES_STATS.may_load(storage)?.unwrap_or_default();
+    stats.num_sales = stats.num_sales.saturating_sub(1);
+    subtract_from_balances(&mut stats.revenue, token, refund);
+
+    if let Some(mut buyer_stats) = BUYER_STATS.may_load(storage, buyer.as_bytes())? {
+        buyer_stats.num_purchases = buyer_stats.num_purchases.saturating_sub(1);
+        subtract_from_balances(&mut buyer_stats.spent, token, refund);
+        if buyer_stats.num_purchases == 0 {
+            BUYER_STATS.remove(storage, buyer.as_bytes());
+            stats.unique_buyers = stats.unique_buyers.saturating_sub(1);
+        } else {
+            BUYER_STATS.save(storage, buyer.as_bytes(), &buyer_stats)?;
+        }
+    }
+    SALES_STATS.save(storage, &stats)
+}
+
+// picks an entry of a loot pool from the hash
+pub(crate) fn pick<'a>(pool: &'a [String], category: &str, hash: u64) -> Result<&'a String, ContractError> {
+    if pool.is_empty() {
+        return Err(ContractError::EmptyTraitPool {
+            category: category.to_string(),
+        });
+    }
+    Ok(&pool[(hash % pool.len() as u64) as usize])
+}
+
+pub(crate) const CHARACTER_DESCRIPTION: &str = "Character Sheet Loot for the Lootopia Metaverse";
+
+// in escrow mode a payment stays here until withdrawn, otherwise it goes
+// straight to the treasury
+pub(crate) fn collect_payment(
+    deps: DepsMut,
+    config: &Config,
+    token: &str,
+    amount: Uint128,
+) -> Result<Option<CosmosMsg>, ContractError> {
+    if amount.is_zero() {
+        return Ok(None);
+    }
+    if config.escrow {
+        add_to_ledger(deps.storage, SALES_BALANCE, token, amount)?;
+        return Ok(None);
+    }
+    let to_withdraw = Asset {
+        info: AssetInfo::Token {
+            contract_addr: token.to_string(),
+        },
+        amount,
+    };
+    let treasury = deps.api.addr_validate(&config.treasury)?;
+    Ok(Some(to_withdraw.into_msg(&deps.querier, treasury)?))
+}
+
+fn execute_buy(
+    mut deps: DepsMut, 
+    env: Env,
+    mut info: MessageInfo,
+    token_sent: String, 
+    amount_sent: Uint128, 
+    buyer: String,
+    mut mint_msg: MintMsg,
+) -> Result<Response, ContractError> {
+
+
+    let cw721_contract = LootopiaNFTContract::default();
+    // verify token_sent == payment_token
+    let config = CONFIG.load(deps.storage)?;
+    if let Some(start) = config.sale_start {
+        if env.block.time < start {
+            return Err(ContractError::SaleNotStarted { start });
+        }
+    }
+    // the cw20 sender is set by the token contract, it proves nothing about
+    // the buyer, so admins pay like everyone else and mint reserved tokens
+    // with Mint
+    if config.payment_token != token_sent {
+        return Err(ContractError::InvalidPaymentToken {
+            expected: config.payment_token,
+            got: token_sent,
+        });
+    } // verify amount_sent >= price
+    if amount_sent < config.price {
+        return Err(ContractError::InsufficientPayment {
+            required: config.price,
+            sent: amount_sent,
+        });
+    }
+
+    let mut loot = LOOT.load(deps.storage)?;
+
+    // the reserved allocation is kept out of the sale, the cap holds for everyone
+    let sold = loot.minted() - loot.reserved_minted;
+    if loot.minted() >= loot.num_items || sold >= loot.num_items - loot.num_reserved {
+        return Err(ContractError::SoldOut {});
+    }
+
+    // check limit per address
+    let mut by_address = MINTS_BY_ADDRESS
+    .load(deps.storage, buyer.as_bytes())
+    .unwrap_or(vec![]);
+
+    if by_address.len() >= config.limit_per_address as usize {
+        return Err(ContractError::MaxMintsPerAddress {});
+    }
+    by_address.push(loot.curr_num_items);
     MINTS_BY_ADDRESS.save(deps.storage, buyer.as_bytes(), &by_address)?;
 
     // check if whitelist is enabled
-    if config.whitelist == true && &buyer != &admin {
+    if config.whitelist {
         let whitelist_by_address = WHITELIST_BY_ADDRESS
-        .load(deps.storage, &buyer.as_bytes())
+        .load(deps.storage, buyer.as_bytes())
         .unwrap_or(vec![]);
 
 
         // if whitelist_by_address doesn't exist, then it's not whitelisted
-        if whitelist_by_address.len() > 0 as usize {
+        if !whitelist_by_address.is_empty() {
             // check if whitelisted
             if whitelist_by_address[0] != 1 {
                 return Err(ContractError::NotWhitelisted {});
@@ -280,39 +1254,19 @@ fn execute_buy(
     .concat();
     let hash = get_hash(&rng_seed);
     // pick random
-    let selected_name = loot
-        .names
-        .get((hash % loot.names.len() as u64) as usize)
-        .ok_or(ContractError::Failed {})?;
-    let selected_origin = loot
-        .origins
-        .get((hash % loot.origins.len() as u64) as usize)
-        .ok_or(ContractError::Failed {})?;
-    let selected_profession = loot
-        .professions
-        .get((hash % loot.professions.len() as u64) as usize)
-        .ok_or(ContractError::Failed {})?;
-    let selected_obsession = loot
-        .obsessions
-        .get((hash % loot.obsessions.len() as u64) as usize)
-        .ok_or(ContractError::Failed {})?;
-    let selected_talent = loot
-        .talents
-        .get((hash % loot.talents.len() as u64) as usize)
-        .ok_or(ContractError::Failed {})?;
-    let selected_skill = loot
-        .skills
-        .get((hash % loot.skills.len() as u64) as usize)
-        .ok_or(ContractError::Failed {})?;
-    let selected_alignment = loot
-        .alignments
-        .get((hash % loot.alignments.len() as u64) as usize)
-        .ok_or(ContractError::Failed {})?;
+    let selected_name = pick(&loot.names, "names", hash)?;
+    let selected_origin = pick(&loot.origins, "origins", hash)?;
+    let selected_profession = pick(&loot.professions, "professions", hash)?;
+    let selected_obsession = pick(&loot.obsessions, "obsessions", hash)?;
+    let selected_talent = pick(&loot.talents, "talents", hash)?;
+    let selected_skill = pick(&loot.skills, "skills", hash)?;
+    let selected_alignment = pick(&loot.alignments, "alignments", hash)?;
     // increase number of items
     loot.curr_num_items += 1;
     LOOT.save(deps.storage, &loot)?;
 
-    info.sender = deps.api.addr_validate(&config.payment_token)?;
+    // the payment token can change, the minter stays the same
+    info.sender = cw721_contract.minter.load(deps.storage)?;
     
         // We need to construct the NFT mint message here.
  
@@ -323,7 +1277,7 @@ fn execute_buy(
         name: Some(selected_name.clone()),
         image: None,
         animation_url: None,
-        description: Some("Character Sheet Loot for the Lootopia Metaverse".to_string()),
+        description: Some(CHARACTER_DESCRIPTION.to_string()),
         background_color: None,
         youtube_url: None,
         image_data: None,
@@ -361,24 +1315,39 @@ fn execute_buy(
             },
         ]),
     };
+    let event = buy_event(
+        &buyer,
+        &mint_msg.token_id,
+        amount_sent,
+        &token_sent,
+        selected_name,
+        extension.attributes.as_deref().unwrap_or_default(),
+    );
     mint_msg.extension = Some(extension);
+    index_traits(deps.storage, &mint_msg.token_id, &mint_msg.extension)?;
     // if both ok, mint buyer a token
 
     
-      // TO FIX:
-
-    let cfg = CONFIG.load(deps.storage)?;
-    let to_withdraw = Asset {
-        info: AssetInfo::Token {
-                contract_addr: cfg.payment_token.clone(),
+    record_sale(deps.storage, &buyer, &token_sent, amount_sent)?;
+    PURCHASES.save(
+        deps.storage,
+        &mint_msg.token_id,
+        &Purchase {
+            buyer: deps.api.addr_validate(&buyer)?,
+            price: TokenBalance {
+                token: token_sent.clone(),
+                amount: amount_sent,
+            },
+            height: env.block.height,
         },
-        amount: amount_sent.clone(),
-    };
-    let treasury = deps.api.addr_validate(&cfg.treasury)?;
-    
-    let querier = deps.querier.clone();
+    )?;
+
+    let payment = collect_payment(deps.branch(), &config, &token_sent, amount_sent)?;
+
     let response = cw721_contract.mint(deps, env, info, mint_msg)?;
-    Ok(response.add_message(to_withdraw.into_msg(&querier, treasury)?))
+    Ok(response
+        .add_messages(payment)
+        .add_event(event))
 }
 
 
@@ -388,11 +1357,164 @@ pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
         QueryMsg::Whitelisted { start_after, limit } => {
             to_binary(&try_whitelisted(deps, start_after, limit)?)
         }
+        QueryMsg::NftInfoBatch { token_ids } => to_binary(&try_nft_info_batch(deps, token_ids)?),
+        QueryMsg::AllTokensWithInfo { start_after, limit } => {
+            to_binary(&try_all_tokens_with_info(deps, start_after, limit)?)
+        }
+        QueryMsg::TokensWithInfo {
+            owner,
+            start_after,
+            limit,
+        } => to_binary(&try_tokens_with_info(deps, owner, start_after, limit)?),
+        QueryMsg::CharacterSheet { token_id } => to_binary(&try_character_sheet(deps, token_id)?),
+        QueryMsg::AdminProposals {} => to_binary(&try_admin_proposals(deps)?),
+        QueryMsg::PauseFlags {} => to_binary(&try_pause_flags(deps)?),
+        QueryMsg::SalesBalance {} => to_binary(&try_sales_balance(deps)?),
+        QueryMsg::Soulbound { token_id } => to_binary(&SoulboundResponse {
+            soulbound: is_soulbound(deps.storage, &token_id)?,
+        }),
+        QueryMsg::LockStatus { token_id } => to_binary(&try_lock_status(deps, env, token_id)?),
+        QueryMsg::UserOf { token_id } => to_binary(&rental::query_user_of(deps, env, token_id)?),
+        QueryMsg::RentalListing { token_id } => to_binary(&RENTAL_LISTINGS.may_load(deps.storage, &token_id)?),
+        QueryMsg::SupplyInfo {} => to_binary(&try_supply_info(deps)?),
+        QueryMsg::RefundInfo {} => to_binary(&try_refund_info(deps)?),
+        QueryMsg::SalesStats {} => to_binary(&try_sales_stats(deps)?),
+        QueryMsg::BuyerStats { address } => to_binary(&try_buyer_stats(deps, address)?),
+        QueryMsg::RolesOf { address } => to_binary(&try_roles_of(deps, address)?),
+        QueryMsg::RoleMembers { role, start_after, limit } => {
+            to_binary(&try_role_members(deps, role, start_after, limit)?)
+        }
+        QueryMsg::Progression { token_id } => to_binary(&progression::query_progression(deps, token_id)?),
+        QueryMsg::LevelConfig {} => to_binary(&LEVEL_CONFIG.may_load(deps.storage)?.unwrap_or_default()),
+        QueryMsg::TokensByTrait {
+            trait_type,
+            value,
+            start_after,
+            limit,
+        } => to_binary(&traits::query_tokens_by_trait(deps, trait_type, value, start_after, limit)?),
+        QueryMsg::TraitDistribution { start_after, limit } => {
+            to_binary(&traits::query_trait_distribution(deps, start_after, limit)?)
+        }
+        QueryMsg::Rarity { token_id } => to_binary(&traits::query_rarity(deps, token_id)?),
+        QueryMsg::Listing { token_id } => to_binary(&LISTINGS.may_load(deps.storage, &token_id)?),
+        QueryMsg::Listings { start_after, limit } => to_binary(&market::query_listings(deps, start_after, limit)?),
+        QueryMsg::MarketConfig {} => to_binary(&MARKET_CONFIG.may_load(deps.storage)?.unwrap_or_default()),
+        QueryMsg::Offer { offer_id } => to_binary(&market::query_offer(deps, offer_id)?),
+        QueryMsg::OffersForToken {
+            token_id,
+            start_after,
+            limit,
+        } => to_binary(&market::query_offers_for_token(deps, token_id, start_after, limit)?),
+        QueryMsg::OffersForTrait {
+            trait_type,
+            value,
+            start_after,
+            limit,
+        } => to_binary(&market::query_offers_for_trait(deps, trait_type, value, start_after, limit)?),
+        QueryMsg::OffersByBidder {
+            bidder,
+            start_after,
+            limit,
+        } => to_binary(&market::query_offers_by_bidder(deps, bidder, start_after, limit)?),
+        QueryMsg::Auction { token_id } => to_binary(&AUCTIONS.may_load(deps.storage, &token_id)?),
+        QueryMsg::Auctions { start_after, limit } => to_binary(&auction::query_auctions(deps, start_after, limit)?),
+        QueryMsg::FusionConfig {} => to_binary(&FUSION_CONFIG.may_load(deps.storage)?),
+        QueryMsg::Lineage { token_id } => to_binary(&LineageResponse {
+            parents: LINEAGE.may_load(deps.storage, &token_id)?.unwrap_or_default(),
+        }),
+        QueryMsg::Equipment { token_id } => to_binary(&equipment::query_equipment(deps, token_id)?),
+        QueryMsg::ItemCollections { start_after, limit } => {
+            to_binary(&equipment::query_item_collections(deps, start_after, limit)?)
+        }
+        QueryMsg::NftInfo { token_id } => to_binary(&try_nft_info(deps, token_id)?),
+        QueryMsg::AllNftInfo {
+            token_id,
+            include_expired,
+        } => to_binary(&try_all_nft_info(deps, env, token_id, include_expired)?),
         // CW721 methods
         _ => LootopiaNFTContract::default().query(deps, env, msg.into()),
     }
 }
 
+// the metadata as stored, with the level added
+fn try_nft_info(deps: Deps, token_id: String) -> StdResult<NftInfoResponse<Extension>> {
+    let mut info = LootopiaNFTContract::default().nft_info(deps, token_id.clone())?;
+    info.extension = with_level(deps.storage, &token_id, info.extension)?;
+    Ok(info)
+}
+
+fn try_all_nft_info(
+    deps: Deps,
+    env: Env,
+    token_id: String,
+    include_expired: Option<bool>,
+) -> StdResult<AllNftInfoResponse<Extension>> {
+    let mut info = LootopiaNFTContract::default().all_nft_info(
+        deps,
+        env,
+        token_id.clone(),
+        include_expired.unwrap_or(false),
+    )?;
+    info.info.extension = with_level(deps.storage, &token_id, info.info.extension)?;
+    Ok(info)
+}
+
+fn token_with_info(storage: &dyn Storage, token_id: String, token: TokenInfo<Extension>) -> StdResult<TokenWithInfo> {
+    Ok(TokenWithInfo {
+        extension: with_level(storage, &token_id, token.extension)?,
+        token_id,
+        owner: token.owner.to_string(),
+        token_uri: token.token_uri,
+    })
+}
+
+fn try_nft_info_batch(deps: Deps, token_ids: Vec<String>) -> StdResult<TokensWithInfoResponse> {
+    const MAX_BATCH: usize = 30;
+    if token_ids.len() > MAX_BATCH {
+        return Err(StdError::generic_err(format!("at most {} token ids per batch", MAX_BATCH)));
+    }
+    let tokens: StdResult<Vec<_>> = token_ids
+        .into_iter()
+        .map(|token_id| {
+            let token = LootopiaNFTContract::default().tokens.load(deps.storage, &token_id)?;
+            token_with_info(deps.storage, token_id, token)
+        })
+        .collect();
+    Ok(TokensWithInfoResponse { tokens: tokens? })
+}
+
+fn try_all_tokens_with_info(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<TokensWithInfoResponse> {
+    const MAX_LIMIT: u32 = 30;
+    const DEFAULT_LIMIT: u32 = 10;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+
+    let tokens: StdResult<Vec<_>> = TOKENS
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (token_id, token) = item?;
+            token_with_info(deps.storage, String::from_utf8(token_id)?, token)
+        })
+        .collect();
+    Ok(TokensWithInfoResponse { tokens: tokens? })
+}
+
+// the owner index is paged by token id, so the cw721 query can be reused
+fn try_tokens_with_info(
+    deps: Deps,
+    owner: String,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<TokensWithInfoResponse> {
+    let token_ids = LootopiaNFTContract::default().tokens(deps, owner, start_after, limit)?.tokens;
+    try_nft_info_batch(deps, token_ids)
+}
+
 fn try_whitelisted(deps: Deps, start_after: Option<String>, limit: Option<u32>) -> StdResult<AllWhitelisted> {
     // settings for pagination
     const MAX_LIMIT: u32 = 30;
@@ -411,31 +1533,1686 @@ fn try_whitelisted(deps: Deps, start_after: Option<String>, limit: Option<u32>)
     })
 }
 
+fn try_pause_flags(deps: Deps) -> StdResult<PauseFlags> {
+    Ok(PAUSE_FLAGS.may_load(deps.storage)?.unwrap_or_default())
+}
+
+fn ledger_balances(deps: Deps, ledger: Map<&[u8], Uint128>) -> StdResult<Vec<TokenBalance>> {
+    ledger
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| {
+            let (token, amount) = item?;
+            Ok(TokenBalance {
+                token: String::from_utf8(token)?,
+                amount,
+            })
+        })
+        .collect()
+}
+
+fn try_sales_balance(deps: Deps) -> StdResult<SalesBalanceResponse> {
+    Ok(SalesBalanceResponse {
+        balances: ledger_balances(deps, SALES_BALANCE)?,
+    })
+}
+
+fn try_lock_status(deps: Deps, env: Env, token_id: String) -> StdResult<LockStatusResponse> {
+    let lock = active_lock(deps.storage, &env, &token_id)?;
+    Ok(LockStatusResponse {
+        locked: lock.is_some(),
+        locker: lock.as_ref().map(|lock| lock.locker.to_string()),
+        until: lock.map(|lock| lock.until),
+    })
+}
+
+fn try_supply_info(deps: Deps) -> StdResult<SupplyInfoResponse> {
+    let loot = LOOT.load(deps.storage)?;
+    Ok(SupplyInfoResponse {
+        num_items: loot.num_items,
+        minted: loot.minted(),
+        num_reserved: loot.num_reserved,
+        reserved_minted: loot.reserved_minted,
+        fused: loot.fused,
+        refunded: loot.refunded,
+    })
+}
+
+fn try_refund_info(deps: Deps) -> StdResult<RefundInfoResponse> {
+    Ok(RefundInfoResponse {
+        refund_config: REFUND_CONFIG.may_load(deps.storage)?,
+        reserve: ledger_balances(deps, REFUND_RESERVE)?,
+    })
+}
+
+fn try_sales_stats(deps: Deps) -> StdResult<SalesStats> {
+    Ok(SALES_STATS.may_load(deps.storage)?.unwrap_or_default())
+}
+
+fn try_buyer_stats(deps: Deps, address: String) -> StdResult<BuyerStats> {
+    let address = deps.api.addr_validate(&address)?;
+    Ok(BUYER_STATS
+        .may_load(deps.storage, address.as_bytes())?
+        .unwrap_or_default())
+}
+
+fn try_roles_of(deps: Deps, address: String) -> StdResult<RolesResponse> {
+    let address = deps.api.addr_validate(&address)?;
+    Ok(RolesResponse {
+        roles: roles_of(deps.storage, &address)?,
+    })
+}
+
+fn try_role_members(
+    deps: Deps,
+    role: Role,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<RoleMembersResponse> {
+    const MAX_LIMIT: u32 = 30;
+    const DEFAULT_LIMIT: u32 = 10;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+
+    let members: StdResult<Vec<_>> = ROLES
+        .range(deps.storage, start, None, Order::Ascending)
+        .filter(|item| match item {
+            Ok((_, roles)) => roles.contains(&role),
+            Err(_) => true,
+        })
+        .take(limit)
+        .map(|item| {
+            let (address, _) = item?;
+            String::from_utf8(address).map_err(StdError::from)
+        })
+        .collect();
+
+    Ok(RoleMembersResponse { members: members? })
+}
+
+fn try_admin_proposals(deps: Deps) -> StdResult<AdminProposalsResponse> {
+    Ok(AdminProposalsResponse {
+        admin: ADMIN_PROPOSAL.may_load(deps.storage)?,
+        whitelist_admin: WHITELIST_ADMIN_PROPOSAL.may_load(deps.storage)?,
+    })
+}
+
+fn try_character_sheet(deps: Deps, token_id: String) -> StdResult<CharacterSheetResponse> {
+    let token = LootopiaNFTContract::default().tokens.load(deps.storage, &token_id)?;
+    // older deployments have no style saved
+    let style = SVG_STYLE.may_load(deps.storage)?.unwrap_or_default();
+    let svg = render_character_sheet(&token.extension.unwrap_or_default(), &style);
+    Ok(CharacterSheetResponse { svg })
+}
+
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn migrate(
     deps: DepsMut,
     _env: Env,
-    msg: MigrateMsg<Config>,
+    _msg: MigrateMsg,
 ) -> Result<Response, ContractError> {
-    match msg {
-        MigrateMsg { version, config } => try_migrate(deps, version, config),
-    }
+    migrations::migrate(deps)
 }
 
-fn try_migrate(
-    deps: DepsMut,
-    version: String,
-    config: Option<Config>,
-) -> Result<Response, ContractError> {
-    let contract_version = get_contract_version(deps.storage)?;
-    set_contract_version(deps.storage, contract_version.contract, version)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info, MockApi, MockQuerier, MockStorage};
+    use cosmwasm_std::OwnedDeps;
+    use crate::msg::{EquipmentResponse, ProgressionResponse, RarityResponse, TraitDistributionResponse};
+    use cosmwasm_std::{Event, WasmMsg};
+    use crate::msg::{AuctionLot, UserOfResponse};
+    use cw20::Cw20ExecuteMsg;
+    use crate::roles::has_role;
+    use crate::state::{Auction, FusionConfig, LevelConfig, Listing, OfferTarget};
+
+    const ADMIN: &str = "admin";
+    const TOKEN: &str = "token";
+    const MINTER: &str = "minter";
+    const PRICE: u128 = 100;
 
-    if config.is_some() {
-        CONFIG.save(deps.storage, &config.unwrap())?
+    fn loot_list(prefix: &str) -> Vec<String> {
+        vec![format!("{} 1", prefix), format!("{} 2", prefix)]
     }
 
-    Ok(Response::new()
-        .add_attribute("method", "try_migrate")
-        .add_attribute("version", contract_version.version))
-}
\ No newline at end of file
+    fn setup(num_items: u64, num_reserved: u64) -> OwnedDeps<MockStorage, MockApi, MockQuerier> {
+        let mut deps = mock_dependencies(&[]);
+        let msg = InstantiateMsg {
+            name: "Lootopia".to_string(),
+            symbol: "LOOT".to_string(),
+            // not the payment token, the sale mints as the stored minter
+            minter: MINTER.to_string(),
+            payment_token: TOKEN.to_string(),
+            price: Uint128::from(PRICE),
+            treasury: "treasury".to_string(),
+            limit_per_address: 10,
+            names: loot_list("name"),
+            origins: loot_list("origin"),
+            professions: loot_list("profession"),
+            obsessions: loot_list("obsession"),
+            talents: loot_list("talent"),
+            skills: loot_list("skill"),
+            alignments: loot_list("alignment"),
+            num_items,
+            num_reserved: Some(num_reserved),
+            whitelist: false,
+            whitelist_admin: ADMIN.to_string(),
+            admin: ADMIN.to_string(),
+            svg_style: None,
+            escrow: None,
+            sale_start: None,
+            soulbound: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+        deps
+    }
+
+    fn buy(deps: DepsMut, buyer: &str) -> Result<Response, ContractError> {
+        buy_with(deps, buyer, TOKEN, PRICE)
+    }
+
+    fn buy_with(deps: DepsMut, buyer: &str, token: &str, amount: u128) -> Result<Response, ContractError> {
+        let mint_msg = MintMsg {
+            token_id: String::new(),
+            owner: buyer.to_string(),
+            token_uri: None,
+            extension: None,
+        };
+        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: buyer.to_string(),
+            amount: Uint128::from(amount),
+            msg: to_binary(&ReceiveMsg::Buy {
+                mint_msg: Box::new(mint_msg),
+            })
+            .unwrap(),
+        });
+        execute(deps, mock_env(), mock_info(token, &[]), msg)
+    }
+
+    fn mint_reserved(deps: DepsMut, sender: &str) -> Result<Response, ContractError> {
+        let msg = ExecuteMsg::Mint(MintMsg {
+            token_id: String::new(),
+            owner: "team".to_string(),
+            token_uri: None,
+            extension: None,
+        });
+        execute(deps, mock_env(), mock_info(sender, &[]), msg)
+    }
+
+    fn owner_of(deps: Deps, token_id: &str) -> Option<Addr> {
+        LootopiaNFTContract::default()
+            .tokens
+            .may_load(deps.storage, token_id)
+            .unwrap()
+            .map(|token| token.owner)
+    }
+
+    #[test]
+    fn admin_handover_needs_an_unexpired_acceptance() {
+        let mut deps = setup(3, 0);
+        let env = mock_env();
+        let propose = ExecuteMsg::ProposeAdmin {
+            new_admin: "successor".to_string(),
+            expiry: Some(Expiration::AtHeight(env.block.height + 10)),
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("successor", &[]), propose.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let expired = ExecuteMsg::ProposeAdmin {
+            new_admin: "successor".to_string(),
+            expiry: Some(Expiration::AtHeight(env.block.height)),
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info(ADMIN, &[]), expired).unwrap_err();
+        assert_eq!(err, ContractError::Expired {});
+
+        // a cancelled proposal can't be accepted
+        execute(deps.as_mut(), env.clone(), mock_info(ADMIN, &[]), propose.clone()).unwrap();
+        execute(deps.as_mut(), env.clone(), mock_info(ADMIN, &[]), ExecuteMsg::CancelAdminProposal {}).unwrap();
+        let err = execute(deps.as_mut(), env.clone(), mock_info("successor", &[]), ExecuteMsg::AcceptAdmin {}).unwrap_err();
+        assert_eq!(err, ContractError::NoPendingProposal {});
+        let err = execute(deps.as_mut(), env.clone(), mock_info(ADMIN, &[]), ExecuteMsg::CancelAdminProposal {}).unwrap_err();
+        assert_eq!(err, ContractError::NoPendingProposal {});
+
+        // nor an expired one
+        execute(deps.as_mut(), env.clone(), mock_info(ADMIN, &[]), propose.clone()).unwrap();
+        let mut later = env.clone();
+        later.block.height += 10;
+        let err = execute(deps.as_mut(), later, mock_info("successor", &[]), ExecuteMsg::AcceptAdmin {}).unwrap_err();
+        assert_eq!(err, ContractError::ProposalExpired {});
+
+        // only the proposed address accepts
+        execute(deps.as_mut(), env.clone(), mock_info(ADMIN, &[]), propose).unwrap();
+        let err = execute(deps.as_mut(), env.clone(), mock_info("intruder", &[]), ExecuteMsg::AcceptAdmin {}).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        execute(deps.as_mut(), env.clone(), mock_info("successor", &[]), ExecuteMsg::AcceptAdmin {}).unwrap();
+
+        assert_eq!(CONFIG.load(&deps.storage).unwrap().admin, "successor");
+        assert!(has_role(&deps.storage, &Addr::unchecked("successor"), Role::Admin).unwrap());
+        assert!(!has_role(&deps.storage, &Addr::unchecked(ADMIN), Role::Admin).unwrap());
+        let proposals: AdminProposalsResponse =
+            from_binary(&query(deps.as_ref(), env, QueryMsg::AdminProposals {}).unwrap()).unwrap();
+        assert_eq!(proposals.admin, None);
+    }
+
+    #[test]
+    fn whitelist_admin_handover_moves_the_role() {
+        let mut deps = setup(3, 0);
+        let propose = ExecuteMsg::ProposeWhitelistAdmin {
+            new_admin: "curator".to_string(),
+            expiry: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), propose).unwrap();
+        execute(deps.as_mut(), mock_env(), mock_info("curator", &[]), ExecuteMsg::AcceptWhitelistAdmin {}).unwrap();
+        assert_eq!(CONFIG.load(&deps.storage).unwrap().whitelist_admin, "curator");
+        assert!(has_role(&deps.storage, &Addr::unchecked("curator"), Role::WhitelistManager).unwrap());
+    }
+
+    #[test]
+    fn roles_gate_the_handlers() {
+        let mut deps = setup(3, 0);
+        let pause = ExecuteMsg::SetPauseFlags {
+            buying: Some(true),
+            metadata_updates: None,
+            transfers: None,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("ops", &[]), pause.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        let grant = ExecuteMsg::GrantRole {
+            address: "ops".to_string(),
+            role: Role::Pauser,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("ops", &[]), grant.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), grant).unwrap();
+        execute(deps.as_mut(), mock_env(), mock_info("ops", &[]), pause.clone()).unwrap();
+
+        // a pauser is not a treasurer
+        let withdraw = ExecuteMsg::WithdrawSales {
+            amount: Uint128::from(1u128),
+            payment_token: None,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("ops", &[]), withdraw).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        let roles: RolesResponse = from_binary(
+            &query(deps.as_ref(), mock_env(), QueryMsg::RolesOf { address: "ops".to_string() }).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(roles.roles, vec![Role::Pauser]);
+
+        let revoke = ExecuteMsg::RevokeRole {
+            address: "ops".to_string(),
+            role: Role::Pauser,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), revoke).unwrap();
+        let err = execute(deps.as_mut(), mock_env(), mock_info("ops", &[]), pause).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        // the config admin only goes away through a handover
+        let revoke_admin = ExecuteMsg::RevokeRole {
+            address: ADMIN.to_string(),
+            role: Role::Admin,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), revoke_admin).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+    }
+
+    #[test]
+    fn admins_cannot_be_impersonated_through_cw20_receive() {
+        let mut deps = setup(3, 0);
+        // a fake token claiming the admin sent nothing
+        let err = buy_with(deps.as_mut(), ADMIN, "fake_token", 0).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InvalidPaymentToken {
+                expected: TOKEN.to_string(),
+                got: "fake_token".to_string(),
+            }
+        );
+        let err = buy_with(deps.as_mut(), ADMIN, TOKEN, 0).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InsufficientPayment {
+                required: Uint128::from(PRICE),
+                sent: Uint128::zero(),
+            }
+        );
+        assert_eq!(owner_of(deps.as_ref(), "1"), None);
+    }
+
+    #[test]
+    fn each_pause_flag_halts_its_operation() {
+        let mut deps = setup(3, 0);
+        buy(deps.as_mut(), "buyer").unwrap();
+        let pause = ExecuteMsg::SetPauseFlags {
+            buying: Some(true),
+            metadata_updates: Some(true),
+            transfers: Some(true),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), pause).unwrap();
+
+        let paused = |operation: &str| ContractError::Paused {
+            operation: operation.to_string(),
+        };
+        assert_eq!(buy(deps.as_mut(), "buyer").unwrap_err(), paused("buying"));
+        // and every other payment
+        let pay = |msg: ReceiveMsg| {
+            ExecuteMsg::Receive(Cw20ReceiveMsg {
+                sender: "buyer".to_string(),
+                amount: Uint128::from(PRICE),
+                msg: to_binary(&msg).unwrap(),
+            })
+        };
+        let token_id = || "1".to_string();
+        let payments = vec![
+            ReceiveMsg::BuyListing { token_id: token_id() },
+            ReceiveMsg::MakeOffer {
+                target: OfferTarget::Token { token_id: token_id() },
+                expires: None,
+            },
+            ReceiveMsg::Rent { token_id: token_id() },
+            ReceiveMsg::Bid { token_id: token_id() },
+            ReceiveMsg::Fuse {
+                token_ids: [token_id(), "2".to_string()],
+            },
+        ];
+        for payment in payments {
+            let err = execute(deps.as_mut(), mock_env(), mock_info(TOKEN, &[]), pay(payment)).unwrap_err();
+            assert_eq!(err, paused("buying"));
+        }
+        fund_refund_reserve(deps.as_mut(), PRICE);
+        let update = ExecuteMsg::UpdateAllMetadata {
+            token_id: "1".to_string(),
+            extension: None,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), update).unwrap_err();
+        assert_eq!(err, paused("metadata_updates"));
+        let transfer = ExecuteMsg::TransferNft {
+            recipient: "friend".to_string(),
+            token_id: "1".to_string(),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("buyer", &[]), transfer.clone()).unwrap_err();
+        assert_eq!(err, paused("transfers"));
+
+        // flags left out are unchanged
+        let resume = ExecuteMsg::SetPauseFlags {
+            buying: None,
+            metadata_updates: None,
+            transfers: Some(false),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), resume).unwrap();
+        execute(deps.as_mut(), mock_env(), mock_info("buyer", &[]), transfer).unwrap();
+        assert_eq!(buy(deps.as_mut(), "buyer").unwrap_err(), paused("buying"));
+    }
+
+    #[test]
+    fn invalid_configs_are_rejected() {
+        let mut deps = setup(3, 0);
+        let update = |update: UpdateConfigMsg| ExecuteMsg::UpdateConfig(update);
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADMIN, &[]),
+            update(UpdateConfigMsg {
+                price: Some(Uint128::zero()),
+                ..UpdateConfigMsg::default()
+            }),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InvalidConfig {
+                reason: "price must be non-zero unless free mint is enabled".to_string()
+            }
+        );
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADMIN, &[]),
+            update(UpdateConfigMsg {
+                limit_per_address: Some(0),
+                ..UpdateConfigMsg::default()
+            }),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InvalidConfig {
+                reason: "limit_per_address must be at least 1".to_string()
+            }
+        );
+
+        // a free mint may have a zero price
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADMIN, &[]),
+            update(UpdateConfigMsg {
+                price: Some(Uint128::zero()),
+                free_mint: Some(true),
+                ..UpdateConfigMsg::default()
+            }),
+        )
+        .unwrap();
+        assert!(CONFIG.load(&deps.storage).unwrap().price.is_zero());
+    }
+
+    #[test]
+    fn sales_continue_after_changing_the_payment_token() {
+        let mut deps = setup(3, 0);
+        let new_token = ExecuteMsg::UpdateConfig(UpdateConfigMsg {
+            payment_token: Some("new_token".to_string()),
+            ..UpdateConfigMsg::default()
+        });
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), new_token).unwrap();
+
+        let err = buy(deps.as_mut(), "buyer").unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InvalidPaymentToken {
+                expected: "new_token".to_string(),
+                got: TOKEN.to_string(),
+            }
+        );
+        buy_with(deps.as_mut(), "buyer", "new_token", PRICE).unwrap();
+        assert_eq!(owner_of(deps.as_ref(), "1"), Some(Addr::unchecked("buyer")));
+    }
+
+    #[test]
+    fn purchases_and_admin_actions_emit_events() {
+        let mut deps = setup(3, 0);
+        let res = buy(deps.as_mut(), "buyer").unwrap();
+        let buy = res.events.iter().find(|event| event.ty == events::BUY_EVENT).unwrap();
+        let keys: Vec<_> = buy.attributes.iter().map(|attr| attr.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                events::BUYER,
+                events::TOKEN_ID,
+                events::PRICE,
+                events::PAYMENT_TOKEN,
+                "trait_name",
+                "trait_origin",
+                "trait_profession",
+                "trait_obsession",
+                "trait_talent",
+                "trait_skill",
+                "trait_alignment",
+            ]
+        );
+        assert_eq!(buy.attributes[0].value, "buyer");
+        assert_eq!(buy.attributes[2].value, PRICE.to_string());
+
+        let grant = ExecuteMsg::GrantRole {
+            address: "ops".to_string(),
+            role: Role::Pauser,
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), grant).unwrap();
+        assert_eq!(
+            res.events,
+            vec![Event::new(events::ROLE_EVENT)
+                .add_attribute(events::ACTION, "grant")
+                .add_attribute(events::ADDRESS, "ops")
+                .add_attribute(events::ROLE, "pauser")]
+        );
+
+        let update = ExecuteMsg::UpdateConfig(UpdateConfigMsg {
+            price: Some(Uint128::from(2 * PRICE)),
+            ..UpdateConfigMsg::default()
+        });
+        let res = execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), update).unwrap();
+        assert_eq!(
+            res.events,
+            vec![Event::new(events::CONFIG_EVENT)
+                .add_attribute(events::SENDER, ADMIN)
+                .add_attribute("old_price", PRICE.to_string())
+                .add_attribute("new_price", (2 * PRICE).to_string())]
+        );
+
+        let pause = ExecuteMsg::SetPauseFlags {
+            buying: Some(true),
+            metadata_updates: None,
+            transfers: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), pause).unwrap();
+        assert_eq!(res.events[0].ty, events::PAUSE_EVENT);
+    }
+
+    fn cw20_transfer(token: &str, recipient: &str, amount: u128) -> CosmosMsg {
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: token.to_string(),
+            msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: recipient.to_string(),
+                amount: Uint128::from(amount),
+            })
+            .unwrap(),
+            funds: vec![],
+        })
+    }
+
+    #[test]
+    fn proceeds_go_to_the_treasury_unless_escrowed() {
+        let mut deps = setup(3, 0);
+        let res = buy(deps.as_mut(), "buyer").unwrap();
+        let messages: Vec<_> = res.messages.into_iter().map(|msg| msg.msg).collect();
+        assert_eq!(messages, vec![cw20_transfer(TOKEN, "treasury", PRICE)]);
+
+        let escrow = ExecuteMsg::UpdateConfig(UpdateConfigMsg {
+            escrow: Some(true),
+            ..UpdateConfigMsg::default()
+        });
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), escrow).unwrap();
+        let res = buy(deps.as_mut(), "buyer").unwrap();
+        assert!(res.messages.is_empty());
+        let balance: SalesBalanceResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::SalesBalance {}).unwrap()).unwrap();
+        assert_eq!(
+            balance.balances,
+            vec![TokenBalance {
+                token: TOKEN.to_string(),
+                amount: Uint128::from(PRICE),
+            }]
+        );
+
+        // only what was escrowed can be withdrawn
+        let withdraw = |amount: u128| ExecuteMsg::WithdrawSales {
+            amount: Uint128::from(amount),
+            payment_token: None,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), withdraw(PRICE + 1)).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InsufficientSalesBalance {
+                available: Uint128::from(PRICE),
+                requested: Uint128::from(PRICE + 1),
+            }
+        );
+        let res = execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), withdraw(PRICE)).unwrap();
+        assert_eq!(res.messages[0].msg, cw20_transfer(TOKEN, "treasury", PRICE));
+        let err = execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), withdraw(1)).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InsufficientSalesBalance {
+                available: Uint128::zero(),
+                requested: Uint128::from(1u128),
+            }
+        );
+    }
+
+    #[test]
+    fn sales_stats_follow_the_buys() {
+        let mut deps = setup(5, 0);
+        buy(deps.as_mut(), "alice").unwrap();
+        buy(deps.as_mut(), "alice").unwrap();
+        buy(deps.as_mut(), "bob").unwrap();
+
+        let stats: SalesStats =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::SalesStats {}).unwrap()).unwrap();
+        assert_eq!(
+            stats,
+            SalesStats {
+                num_sales: 3,
+                unique_buyers: 2,
+                revenue: vec![TokenBalance {
+                    token: TOKEN.to_string(),
+                    amount: Uint128::from(3 * PRICE),
+                }],
+            }
+        );
+        let alice: BuyerStats = from_binary(
+            &query(deps.as_ref(), mock_env(), QueryMsg::BuyerStats { address: "alice".to_string() }).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(alice.num_purchases, 2);
+        assert_eq!(alice.spent[0].amount, Uint128::from(2 * PRICE));
+        let carol: BuyerStats = from_binary(
+            &query(deps.as_ref(), mock_env(), QueryMsg::BuyerStats { address: "carol".to_string() }).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(carol, BuyerStats::default());
+    }
+
+    fn fund_refund_reserve(deps: DepsMut, amount: u128) {
+        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: ADMIN.to_string(),
+            amount: Uint128::from(amount),
+            msg: to_binary(&ReceiveMsg::FundRefundReserve {}).unwrap(),
+        });
+        execute(deps, mock_env(), mock_info(TOKEN, &[]), msg).unwrap();
+    }
+
+    // what the SendNft of `sender` triggers once the token is ours
+    fn return_token(deps: DepsMut, env: Env, sender: &str, token_id: &str) -> Result<Response, ContractError> {
+        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+            sender: sender.to_string(),
+            token_id: token_id.to_string(),
+            msg: to_binary(&NftReceiveMsg::Refund {}).unwrap(),
+        });
+        let contract = env.contract.address.to_string();
+        execute(deps, env, mock_info(&contract, &[]), msg)
+    }
+
+    #[test]
+    fn refunds_are_checked_and_reverse_the_sale() {
+        let mut deps = setup(5, 0);
+        let set_refunds = |rate: u64| ExecuteMsg::SetRefundConfig {
+            refund_config: Some(RefundConfig {
+                window_blocks: 10,
+                refund_rate: Decimal::percent(rate),
+            }),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), set_refunds(0)).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InvalidConfig {
+                reason: "refund_rate must be above 0 and at most 1".to_string()
+            }
+        );
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), set_refunds(50)).unwrap();
+        buy(deps.as_mut(), "buyer").unwrap();
+        buy(deps.as_mut(), "buyer").unwrap();
+        fund_refund_reserve(deps.as_mut(), 40);
+        let env = mock_env();
+        LootopiaNFTContract::default()
+            .tokens
+            .update(&mut deps.storage, "1", |token| -> StdResult<_> {
+                let mut token = token.unwrap();
+                token.owner = env.contract.address.clone();
+                Ok(token)
+            })
+            .unwrap();
+
+        // only the buyer, within the window
+        let err = return_token(deps.as_mut(), env.clone(), "other", "1").unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let mut late = env.clone();
+        late.block.height += 11;
+        let err = return_token(deps.as_mut(), late, "buyer", "1").unwrap_err();
+        assert_eq!(err, ContractError::RefundWindowClosed {});
+
+        let err = return_token(deps.as_mut(), env.clone(), "buyer", "1").unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InsufficientRefundReserve {
+                available: Uint128::from(40u128),
+                requested: Uint128::from(PRICE / 2),
+            }
+        );
+        fund_refund_reserve(deps.as_mut(), 60);
+
+        let res = return_token(deps.as_mut(), env, "buyer", "1").unwrap();
+        assert_eq!(res.messages[0].msg, cw20_transfer(TOKEN, "buyer", PRICE / 2));
+        assert_eq!(owner_of(deps.as_ref(), "1"), None);
+        let refund_info: RefundInfoResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::RefundInfo {}).unwrap()).unwrap();
+        assert_eq!(refund_info.reserve[0].amount, Uint128::from(50u128));
+
+        // the mint of token 1 no longer counts, the one of token 2 does
+        assert_eq!(MINTS_BY_ADDRESS.load(&deps.storage, b"buyer").unwrap(), vec![1]);
+        let stats: SalesStats =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::SalesStats {}).unwrap()).unwrap();
+        assert_eq!(stats.num_sales, 1);
+        assert_eq!(stats.unique_buyers, 1);
+        assert_eq!(stats.revenue[0].amount, Uint128::from(2 * PRICE - PRICE / 2));
+        let buyer: BuyerStats = from_binary(
+            &query(deps.as_ref(), mock_env(), QueryMsg::BuyerStats { address: "buyer".to_string() }).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(buyer.num_purchases, 1);
+    }
+
+    #[test]
+    fn refunded_slots_go_back_on_sale() {
+        let mut deps = setup(1, 0);
+        let set_refunds = ExecuteMsg::SetRefundConfig {
+            refund_config: Some(RefundConfig {
+                window_blocks: u64::MAX,
+                refund_rate: Decimal::one(),
+            }),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), set_refunds).unwrap();
+        fund_refund_reserve(deps.as_mut(), PRICE);
+        buy(deps.as_mut(), "buyer").unwrap();
+        let env = mock_env();
+        LootopiaNFTContract::default()
+            .tokens
+            .update(&mut deps.storage, "1", |token| -> StdResult<_> {
+                let mut token = token.unwrap();
+                token.owner = env.contract.address.clone();
+                Ok(token)
+            })
+            .unwrap();
+        return_token(deps.as_mut(), env, "buyer", "1").unwrap();
+
+        let supply: SupplyInfoResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::SupplyInfo {}).unwrap()).unwrap();
+        assert_eq!(supply.minted, 0);
+        assert_eq!(supply.refunded, 1);
+        // the slot is sold again under a new id, and only once
+        buy(deps.as_mut(), "other").unwrap();
+        assert_eq!(owner_of(deps.as_ref(), "2"), Some(Addr::unchecked("other")));
+        let err = buy(deps.as_mut(), "buyer").unwrap_err();
+        assert_eq!(err, ContractError::SoldOut {});
+    }
+
+    #[test]
+    fn last_sale_is_exactly_num_items() {
+        let mut deps = setup(3, 0);
+        for _ in 0..3 {
+            buy(deps.as_mut(), "buyer").unwrap();
+        }
+        assert_eq!(owner_of(deps.as_ref(), "3"), Some(Addr::unchecked("buyer")));
+
+        let err = buy(deps.as_mut(), "buyer").unwrap_err();
+        assert_eq!(err, ContractError::SoldOut {});
+        assert_eq!(owner_of(deps.as_ref(), "4"), None);
+    }
+
+    #[test]
+    fn admin_cannot_buy_past_the_cap() {
+        let mut deps = setup(1, 0);
+        buy(deps.as_mut(), ADMIN).unwrap();
+        let err = buy(deps.as_mut(), ADMIN).unwrap_err();
+        assert_eq!(err, ContractError::SoldOut {});
+    }
+
+    #[test]
+    fn reserved_allocation_is_kept_out_of_the_sale() {
+        let mut deps = setup(3, 1);
+        buy(deps.as_mut(), "buyer").unwrap();
+        buy(deps.as_mut(), "buyer").unwrap();
+        let err = buy(deps.as_mut(), "buyer").unwrap_err();
+        assert_eq!(err, ContractError::SoldOut {});
+
+        // only minters can mint the reserved tokens
+        let err = mint_reserved(deps.as_mut(), "buyer").unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        mint_reserved(deps.as_mut(), ADMIN).unwrap();
+        assert_eq!(owner_of(deps.as_ref(), "3"), Some(Addr::unchecked("team")));
+        let err = mint_reserved(deps.as_mut(), ADMIN).unwrap_err();
+        assert_eq!(err, ContractError::SoldOut {});
+
+        let supply: SupplyInfoResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::SupplyInfo {}).unwrap()).unwrap();
+        assert_eq!(supply.minted, 3);
+        assert_eq!(supply.reserved_minted, 1);
+    }
+
+    #[test]
+    fn reserved_mints_are_capped_by_the_allocation() {
+        let mut deps = setup(3, 1);
+        mint_reserved(deps.as_mut(), ADMIN).unwrap();
+        let err = mint_reserved(deps.as_mut(), ADMIN).unwrap_err();
+        assert_eq!(err, ContractError::ReservedAllocationExhausted {});
+
+        // the sale still gets the rest of the supply
+        buy(deps.as_mut(), "buyer").unwrap();
+        buy(deps.as_mut(), "buyer").unwrap();
+        assert_eq!(owner_of(deps.as_ref(), "3"), Some(Addr::unchecked("buyer")));
+        let err = buy(deps.as_mut(), "buyer").unwrap_err();
+        assert_eq!(err, ContractError::SoldOut {});
+    }
+
+    #[test]
+    fn purchase_errors_tell_what_went_wrong() {
+        let mut deps = setup(3, 0);
+        let err = buy_with(deps.as_mut(), "buyer", "other", PRICE).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InvalidPaymentToken {
+                expected: TOKEN.to_string(),
+                got: "other".to_string(),
+            }
+        );
+
+        let err = buy_with(deps.as_mut(), "buyer", TOKEN, PRICE - 1).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InsufficientPayment {
+                required: Uint128::from(PRICE),
+                sent: Uint128::from(PRICE - 1),
+            }
+        );
+
+        let start = mock_env().block.time.plus_seconds(60);
+        let mut config = CONFIG.load(deps.as_ref().storage).unwrap();
+        config.sale_start = Some(start);
+        CONFIG.save(deps.as_mut().storage, &config).unwrap();
+        let err = buy(deps.as_mut(), "buyer").unwrap_err();
+        assert_eq!(err, ContractError::SaleNotStarted { start });
+    }
+
+    #[test]
+    fn migrate_runs_the_steps_from_the_stored_version() {
+        let mut deps = setup(3, 0);
+        buy(deps.as_mut(), "buyer").unwrap();
+        // a 0.1.0 deployment: cw721-base name, no role registry nor trait index
+        set_contract_version(deps.as_mut().storage, "crates.io:cw721-base", "0.3.1").unwrap();
+        ROLES.remove(deps.as_mut().storage, ADMIN.as_bytes());
+        let token = LootopiaNFTContract::default().tokens.load(deps.as_ref().storage, "1").unwrap();
+        unindex_traits(deps.as_mut().storage, "1", &token.extension).unwrap();
+
+        let res = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+        let steps = res.attributes.iter().find(|attr| attr.key == "steps").unwrap();
+        assert_eq!(steps.value, "0.1.0");
+        assert!(has_role(deps.as_ref().storage, &Addr::unchecked(ADMIN), Role::Admin).unwrap());
+
+        // the trait index is backfilled by the admin, a page at a time
+        let reindex = ExecuteMsg::Reindex {
+            start_after: None,
+            limit: Some(1),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("buyer", &[]), reindex.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let res = execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), reindex).unwrap();
+        let last = res.attributes.iter().find(|attr| attr.key == "last_token_id").unwrap();
+        assert_eq!(last.value, "1");
+        let next = ExecuteMsg::Reindex {
+            start_after: Some(last.value.clone()),
+            limit: Some(1),
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), next).unwrap();
+        let last = res.attributes.iter().find(|attr| attr.key == "last_token_id").unwrap();
+        assert_eq!(last.value, "none");
+
+        let origin = &token.extension.unwrap().attributes.unwrap()[0];
+        let by_trait = traits::query_tokens_by_trait(
+            deps.as_ref(),
+            origin.trait_type.clone(),
+            origin.value.clone(),
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(by_trait.tokens, vec!["1".to_string()]);
+
+        // nothing left to apply
+        let res = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+        let steps = res.attributes.iter().find(|attr| attr.key == "steps").unwrap();
+        assert_eq!(steps.value, "none");
+    }
+
+    #[test]
+    fn migrate_rejects_other_contracts_and_downgrades() {
+        let mut deps = setup(3, 0);
+        set_contract_version(deps.as_mut().storage, "crates.io:cw20-base", CONTRACT_VERSION).unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InvalidContractName {
+                expected: CONTRACT_NAME.to_string(),
+                got: "crates.io:cw20-base".to_string(),
+            }
+        );
+
+        set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "99.0.0").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::CannotDowngrade {
+                stored: "99.0.0".to_string(),
+                target: CONTRACT_VERSION.to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn soulbound_tokens_only_move_through_recovery() {
+        let mut deps = setup(3, 0);
+        let mut config = CONFIG.load(deps.as_ref().storage).unwrap();
+        config.soulbound = true;
+        CONFIG.save(deps.as_mut().storage, &config).unwrap();
+        buy(deps.as_mut(), "buyer").unwrap();
+
+        let transfer = ExecuteMsg::TransferNft {
+            recipient: "friend".to_string(),
+            token_id: "1".to_string(),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("buyer", &[]), transfer.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Soulbound { token_id: "1".to_string() });
+
+        let recover = ExecuteMsg::RecoverToken {
+            token_id: "1".to_string(),
+            recipient: "new_wallet".to_string(),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("buyer", &[]), recover.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), recover).unwrap();
+        assert_eq!(owner_of(deps.as_ref(), "1"), Some(Addr::unchecked("new_wallet")));
+
+        // a per token flag overrides the soulbound mode
+        let unbind = ExecuteMsg::SetSoulbound {
+            token_id: "1".to_string(),
+            soulbound: Some(false),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), unbind).unwrap();
+        execute(deps.as_mut(), mock_env(), mock_info("new_wallet", &[]), transfer).unwrap();
+        assert_eq!(owner_of(deps.as_ref(), "1"), Some(Addr::unchecked("friend")));
+    }
+
+    #[test]
+    fn locked_tokens_cannot_be_transferred() {
+        let mut deps = setup(3, 0);
+        buy(deps.as_mut(), "buyer").unwrap();
+        let lock = ExecuteMsg::Lock {
+            token_id: "1".to_string(),
+            until: Expiration::AtHeight(mock_env().block.height + 100),
+        };
+
+        // the owner has to allow the game first
+        let err = execute(deps.as_mut(), mock_env(), mock_info("game", &[]), lock.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let allow = ExecuteMsg::AllowLocker {
+            locker: "game".to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("buyer", &[]), allow).unwrap();
+        execute(deps.as_mut(), mock_env(), mock_info("game", &[]), lock).unwrap();
+
+        let transfer = ExecuteMsg::TransferNft {
+            recipient: "friend".to_string(),
+            token_id: "1".to_string(),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("buyer", &[]), transfer.clone()).unwrap_err();
+        assert_eq!(err, ContractError::TokenLocked { token_id: "1".to_string() });
+
+        // the lock ends when it expires
+        let mut later = mock_env();
+        later.block.height += 100;
+        let status: LockStatusResponse = from_binary(
+            &query(deps.as_ref(), later.clone(), QueryMsg::LockStatus { token_id: "1".to_string() }).unwrap(),
+        )
+        .unwrap();
+        assert!(!status.locked);
+        execute(deps.as_mut(), later, mock_info("buyer", &[]), transfer).unwrap();
+        assert_eq!(owner_of(deps.as_ref(), "1"), Some(Addr::unchecked("friend")));
+    }
+
+    #[test]
+    fn games_level_up_characters() {
+        let mut deps = setup(3, 0);
+        buy(deps.as_mut(), "buyer").unwrap();
+        let set_levels = ExecuteMsg::SetLevelConfig {
+            level_config: LevelConfig {
+                thresholds: vec![100, 300],
+                stat_points_per_level: 2,
+            },
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), set_levels).unwrap();
+
+        let award = |amount| ExecuteMsg::AwardXp {
+            token_id: "1".to_string(),
+            amount,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("game", &[]), award(100)).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let grant = ExecuteMsg::GrantRole {
+            address: "game".to_string(),
+            role: Role::Game,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), grant).unwrap();
+        // straight past both thresholds
+        execute(deps.as_mut(), mock_env(), mock_info("game", &[]), award(350)).unwrap();
+
+        let progression: ProgressionResponse = from_binary(
+            &query(deps.as_ref(), mock_env(), QueryMsg::Progression { token_id: "1".to_string() }).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            progression,
+            ProgressionResponse {
+                xp: 350,
+                level: 3,
+                stat_points: 4,
+                next_level_xp: None,
+            }
+        );
+
+        let info: NftInfoResponse<Extension> = from_binary(
+            &query(deps.as_ref(), mock_env(), QueryMsg::NftInfo { token_id: "1".to_string() }).unwrap(),
+        )
+        .unwrap();
+        let level = info.extension.unwrap().attributes.unwrap().pop().unwrap();
+        assert_eq!(level.trait_type, "Level");
+        assert_eq!(level.display_type, Some("number".to_string()));
+        assert_eq!(level.value, "3");
+    }
+
+    #[test]
+    fn stat_point_overflow_is_an_error() {
+        let mut deps = setup(3, 0);
+        buy(deps.as_mut(), "buyer").unwrap();
+        let set_levels = ExecuteMsg::SetLevelConfig {
+            level_config: LevelConfig {
+                thresholds: vec![1, 2],
+                stat_points_per_level: u32::MAX,
+            },
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), set_levels).unwrap();
+        let award = ExecuteMsg::AwardXp {
+            token_id: "1".to_string(),
+            amount: 2,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), award).unwrap_err();
+        assert!(matches!(err, ContractError::Std(StdError::Overflow { .. })));
+        assert_eq!(progression::progression_of(&deps.storage, "1").unwrap().level, 1);
+    }
+
+    #[test]
+    fn tokens_without_metadata_get_no_level() {
+        let mut deps = setup(3, 1);
+        mint_reserved(deps.as_mut(), ADMIN).unwrap();
+        let info: NftInfoResponse<Extension> = from_binary(
+            &query(deps.as_ref(), mock_env(), QueryMsg::NftInfo { token_id: "1".to_string() }).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(info.extension, None);
+    }
+
+    #[test]
+    fn equipment_follows_the_character() {
+        let mut deps = setup(3, 0);
+        buy(deps.as_mut(), "buyer").unwrap();
+        let equip = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+            sender: "buyer".to_string(),
+            token_id: "sword".to_string(),
+            msg: to_binary(&NftReceiveMsg::Equip {
+                token_id: "1".to_string(),
+                slot: "hand".to_string(),
+            })
+            .unwrap(),
+        });
+        let err = execute(deps.as_mut(), mock_env(), mock_info("items", &[]), equip.clone()).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::CollectionNotAllowed {
+                collection: "items".to_string()
+            }
+        );
+        let allow = ExecuteMsg::SetItemCollection {
+            collection: "items".to_string(),
+            allowed: true,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), allow).unwrap();
+        execute(deps.as_mut(), mock_env(), mock_info("items", &[]), equip.clone()).unwrap();
+        let err = execute(deps.as_mut(), mock_env(), mock_info("items", &[]), equip).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::SlotTaken {
+                token_id: "1".to_string(),
+                slot: "hand".to_string(),
+            }
+        );
+
+        let transfer = ExecuteMsg::TransferNft {
+            recipient: "friend".to_string(),
+            token_id: "1".to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("buyer", &[]), transfer).unwrap();
+        let equipment: EquipmentResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::Equipment { token_id: "1".to_string() }).unwrap())
+                .unwrap();
+        assert_eq!(equipment.items.len(), 1);
+        assert_eq!(equipment.items[0].slot, "hand");
+        assert_eq!(equipment.items[0].item.token_id, "sword");
+
+        // the new owner takes the item off
+        let unequip = ExecuteMsg::Unequip {
+            token_id: "1".to_string(),
+            slot: "hand".to_string(),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("buyer", &[]), unequip.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let res = execute(deps.as_mut(), mock_env(), mock_info("friend", &[]), unequip).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            WasmMsg::Execute {
+                contract_addr: "items".to_string(),
+                msg: to_binary(&cw721::Cw721ExecuteMsg::TransferNft {
+                    recipient: "friend".to_string(),
+                    token_id: "sword".to_string(),
+                })
+                .unwrap(),
+                funds: vec![],
+            }
+            .into()
+        );
+    }
+
+    #[test]
+    fn rented_characters_keep_their_equipment() {
+        let mut deps = setup(3, 0);
+        buy(deps.as_mut(), "buyer").unwrap();
+        let allow = ExecuteMsg::SetItemCollection {
+            collection: "items".to_string(),
+            allowed: true,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), allow).unwrap();
+        let equip = |item_id: &str| {
+            ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+                sender: "buyer".to_string(),
+                token_id: item_id.to_string(),
+                msg: to_binary(&NftReceiveMsg::Equip {
+                    token_id: "1".to_string(),
+                    slot: item_id.to_string(),
+                })
+                .unwrap(),
+            })
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("items", &[]), equip("sword")).unwrap();
+
+        let list = |duration: u64| ExecuteMsg::ListRental {
+            token_id: "1".to_string(),
+            price: Uint128::from(PRICE),
+            duration,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("buyer", &[]), list(20_000_000_000)).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InvalidConfig {
+                reason: "duration must be at most 31536000 seconds".to_string()
+            }
+        );
+        execute(deps.as_mut(), mock_env(), mock_info("buyer", &[]), list(3600)).unwrap();
+        let rent = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "renter".to_string(),
+            amount: Uint128::from(PRICE),
+            msg: to_binary(&ReceiveMsg::Rent {
+                token_id: "1".to_string(),
+            })
+            .unwrap(),
+        });
+        execute(deps.as_mut(), mock_env(), mock_info(TOKEN, &[]), rent).unwrap();
+
+        let rented = ContractError::TokenRented {
+            token_id: "1".to_string(),
+        };
+        let recover = ExecuteMsg::RecoverToken {
+            token_id: "1".to_string(),
+            recipient: "new_wallet".to_string(),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), recover).unwrap_err();
+        assert_eq!(err, rented);
+        let unequip = ExecuteMsg::Unequip {
+            token_id: "1".to_string(),
+            slot: "sword".to_string(),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("buyer", &[]), unequip.clone()).unwrap_err();
+        assert_eq!(err, rented);
+        let err = execute(deps.as_mut(), mock_env(), mock_info("items", &[]), equip("shield")).unwrap_err();
+        assert_eq!(err, rented);
+
+        // back to the owner once the rental is over
+        let mut later = mock_env();
+        later.block.time = later.block.time.plus_seconds(3600);
+        execute(deps.as_mut(), later.clone(), mock_info("items", &[]), equip("shield")).unwrap();
+        execute(deps.as_mut(), later, mock_info("buyer", &[]), unequip).unwrap();
+    }
+
+    #[test]
+    fn fusion_burns_the_parents_for_a_child() {
+        let mut deps = setup(3, 0);
+        buy(deps.as_mut(), "buyer").unwrap();
+        buy(deps.as_mut(), "buyer").unwrap();
+        let fuse = ExecuteMsg::Fuse {
+            token_ids: ["1".to_string(), "2".to_string()],
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("buyer", &[]), fuse.clone()).unwrap_err();
+        assert_eq!(err, ContractError::FusionDisabled {});
+
+        let set_fusion = ExecuteMsg::SetFusionConfig {
+            fusion_config: Some(FusionConfig {
+                fee: Some(Uint128::from(50u128)),
+                mutation_rate: Decimal::zero(),
+            }),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), set_fusion).unwrap();
+        let err = execute(deps.as_mut(), mock_env(), mock_info("buyer", &[]), fuse).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InsufficientPayment {
+                required: Uint128::from(50u128),
+                sent: Uint128::zero(),
+            }
+        );
+
+        let parents: Vec<_> = ["1", "2"]
+            .iter()
+            .map(|id| LootopiaNFTContract::default().tokens.load(deps.as_ref().storage, id).unwrap())
+            .collect();
+        let fuse = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "buyer".to_string(),
+            amount: Uint128::from(50u128),
+            msg: to_binary(&ReceiveMsg::Fuse {
+                token_ids: ["1".to_string(), "2".to_string()],
+            })
+            .unwrap(),
+        });
+        execute(deps.as_mut(), mock_env(), mock_info(TOKEN, &[]), fuse).unwrap();
+        assert_eq!(owner_of(deps.as_ref(), "1"), None);
+        assert_eq!(owner_of(deps.as_ref(), "3"), Some(Addr::unchecked("buyer")));
+
+        // without mutations every trait comes from a parent
+        let child = LootopiaNFTContract::default().tokens.load(deps.as_ref().storage, "3").unwrap();
+        let child_traits = child.extension.unwrap().attributes.unwrap();
+        for (i, t) in child_traits.iter().enumerate() {
+            assert!(parents
+                .iter()
+                .any(|parent| parent.extension.as_ref().unwrap().attributes.as_ref().unwrap()[i] == *t));
+        }
+        let lineage: LineageResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::Lineage { token_id: "3".to_string() }).unwrap())
+                .unwrap();
+        assert_eq!(lineage.parents, vec!["1".to_string(), "2".to_string()]);
+
+        // the fused token is on top of the supply cap
+        buy(deps.as_mut(), "buyer").unwrap();
+        assert_eq!(owner_of(deps.as_ref(), "4"), Some(Addr::unchecked("buyer")));
+        let err = buy(deps.as_mut(), "buyer").unwrap_err();
+        assert_eq!(err, ContractError::SoldOut {});
+    }
+
+    #[test]
+    fn soulbound_or_paused_characters_cannot_be_fused() {
+        let mut deps = setup(3, 0);
+        buy(deps.as_mut(), "buyer").unwrap();
+        buy(deps.as_mut(), "buyer").unwrap();
+        let set_fusion = ExecuteMsg::SetFusionConfig {
+            fusion_config: Some(FusionConfig {
+                fee: None,
+                mutation_rate: Decimal::zero(),
+            }),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), set_fusion).unwrap();
+        let fuse = ExecuteMsg::Fuse {
+            token_ids: ["1".to_string(), "2".to_string()],
+        };
+
+        let bind = ExecuteMsg::SetSoulbound {
+            token_id: "2".to_string(),
+            soulbound: Some(true),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), bind).unwrap();
+        let err = execute(deps.as_mut(), mock_env(), mock_info("buyer", &[]), fuse.clone()).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::Soulbound {
+                token_id: "2".to_string()
+            }
+        );
+        let unbind = ExecuteMsg::SetSoulbound {
+            token_id: "2".to_string(),
+            soulbound: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), unbind).unwrap();
+
+        let pause = ExecuteMsg::SetPauseFlags {
+            buying: None,
+            metadata_updates: None,
+            transfers: Some(true),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), pause).unwrap();
+        let err = execute(deps.as_mut(), mock_env(), mock_info("buyer", &[]), fuse).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::Paused {
+                operation: "transfers".to_string()
+            }
+        );
+        assert_eq!(owner_of(deps.as_ref(), "1"), Some(Addr::unchecked("buyer")));
+    }
+
+    #[test]
+    fn trait_index_follows_metadata_updates() {
+        let mut deps = setup(3, 0);
+        buy(deps.as_mut(), "buyer").unwrap();
+        let by_profession = |deps: Deps, value: &str| -> Vec<String> {
+            let msg = QueryMsg::TokensByTrait {
+                trait_type: "Profession".to_string(),
+                value: value.to_string(),
+                start_after: None,
+                limit: None,
+            };
+            let tokens: cw721::TokensResponse = from_binary(&query(deps, mock_env(), msg).unwrap()).unwrap();
+            tokens.tokens
+        };
+        let token = LootopiaNFTContract::default().tokens.load(deps.as_ref().storage, "1").unwrap();
+        let mut metadata = token.extension.unwrap();
+        let profession = metadata.attributes.as_ref().unwrap()[1].value.clone();
+        assert_eq!(by_profession(deps.as_ref(), &profession), vec!["1".to_string()]);
+
+        metadata.attributes.as_mut().unwrap()[1].value = "Alchemist".to_string();
+        let update = ExecuteMsg::UpdateAllMetadata {
+            token_id: "1".to_string(),
+            extension: Some(metadata),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), update).unwrap();
+        assert!(by_profession(deps.as_ref(), &profession).is_empty());
+        assert_eq!(by_profession(deps.as_ref(), "Alchemist"), vec!["1".to_string()]);
+
+        burn_token(deps.as_mut().storage, "1").unwrap();
+        assert!(by_profession(deps.as_ref(), "Alchemist").is_empty());
+    }
+
+    #[test]
+    fn rarity_comes_from_trait_counts() {
+        let mut deps = setup(3, 0);
+        // bought in the same block, both get the same traits
+        buy(deps.as_mut(), "buyer").unwrap();
+        buy(deps.as_mut(), "buyer").unwrap();
+        let token = LootopiaNFTContract::default().tokens.load(deps.as_ref().storage, "2").unwrap();
+        let mut metadata = token.extension.unwrap();
+        metadata.attributes.as_mut().unwrap()[1].value = "Alchemist".to_string();
+        let update = ExecuteMsg::UpdateAllMetadata {
+            token_id: "2".to_string(),
+            extension: Some(metadata),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), update).unwrap();
+
+        let distribution = |start_after: Option<(String, String)>, limit| -> TraitDistributionResponse {
+            let msg = QueryMsg::TraitDistribution { start_after, limit };
+            from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap()
+        };
+        let first = distribution(None, Some(4));
+        assert_eq!(first.traits.len(), 4);
+        let last = first.traits.last().unwrap();
+        let rest = distribution(Some((last.trait_type.clone(), last.value.clone())), None);
+        assert_eq!(rest.traits.len(), 3);
+        assert!(!rest.traits.contains(last));
+
+        let distribution = distribution(None, Some(30));
+        assert_eq!(distribution.num_tokens, 2);
+        assert_eq!(distribution.traits.len(), 7);
+        let alchemist = distribution.traits.iter().find(|t| t.value == "Alchemist").unwrap();
+        assert_eq!(alchemist.trait_type, "Profession");
+        assert_eq!(alchemist.count, 1);
+        let origin = distribution.traits.iter().find(|t| t.trait_type == "Origin").unwrap();
+        assert_eq!(origin.count, 2);
+
+        // five shared traits at 2 / 2, the profession at 2 / 1
+        let rarity: RarityResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::Rarity { token_id: "2".to_string() }).unwrap())
+                .unwrap();
+        assert_eq!(rarity.score, Decimal::from_ratio(7u128, 1u128));
+        assert_eq!(rarity.traits[1].count, 1);
+    }
+
+    #[test]
+    fn tokens_come_with_their_info() {
+        let mut deps = setup(3, 0);
+        buy(deps.as_mut(), "buyer").unwrap();
+        buy(deps.as_mut(), "other").unwrap();
+        buy(deps.as_mut(), "buyer").unwrap();
+        let query_tokens = |deps: Deps, msg| -> TokensWithInfoResponse {
+            from_binary(&query(deps, mock_env(), msg).unwrap()).unwrap()
+        };
+
+        let batch = query_tokens(
+            deps.as_ref(),
+            QueryMsg::NftInfoBatch {
+                token_ids: vec!["2".to_string(), "3".to_string()],
+            },
+        );
+        let owners: Vec<_> = batch.tokens.iter().map(|t| t.owner.as_str()).collect();
+        assert_eq!(owners, vec!["other", "buyer"]);
+        assert!(batch.tokens[0].extension.as_ref().unwrap().name.is_some());
+
+        let page = query_tokens(
+            deps.as_ref(),
+            QueryMsg::AllTokensWithInfo {
+                start_after: Some("1".to_string()),
+                limit: Some(1),
+            },
+        );
+        assert_eq!(page.tokens.len(), 1);
+        assert_eq!(page.tokens[0].token_id, "2");
+
+        let owned = query_tokens(
+            deps.as_ref(),
+            QueryMsg::TokensWithInfo {
+                owner: "buyer".to_string(),
+                start_after: None,
+                limit: None,
+            },
+        );
+        let ids: Vec<_> = owned.tokens.iter().map(|t| t.token_id.as_str()).collect();
+        assert_eq!(ids, vec!["1", "3"]);
+
+        let too_many = QueryMsg::NftInfoBatch {
+            token_ids: vec!["1".to_string(); 31],
+        };
+        query(deps.as_ref(), mock_env(), too_many).unwrap_err();
+    }
+
+    #[test]
+    fn transfers_cancel_listings() {
+        let mut deps = setup(3, 0);
+        buy(deps.as_mut(), "buyer").unwrap();
+        let list = ExecuteMsg::ListToken {
+            token_id: "1".to_string(),
+            price: Uint128::from(PRICE),
+            expires: None,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("other", &[]), list.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        execute(deps.as_mut(), mock_env(), mock_info("buyer", &[]), list).unwrap();
+        assert!(LISTINGS.may_load(deps.as_ref().storage, "1").unwrap().is_some());
+
+        let transfer = ExecuteMsg::TransferNft {
+            recipient: "friend".to_string(),
+            token_id: "1".to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("buyer", &[]), transfer).unwrap();
+        let listing: Option<Listing> =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::Listing { token_id: "1".to_string() }).unwrap())
+                .unwrap();
+        assert_eq!(listing, None);
+    }
+
+    #[test]
+    fn unsold_auctions_go_back_to_the_admin() {
+        let mut deps = setup(3, 1);
+        let env = mock_env();
+        let start = ExecuteMsg::StartAuction {
+            lot: AuctionLot::Mint { extension: None },
+            reserve_price: Uint128::from(5 * PRICE),
+            min_increment: Uint128::from(10u128),
+            end_time: env.block.time.plus_seconds(3600),
+            time_extension: None,
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("buyer", &[]), start.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        execute(deps.as_mut(), env.clone(), mock_info(ADMIN, &[]), start.clone()).unwrap();
+
+        // the lot is minted out of the reserved allocation, held by the contract
+        assert_eq!(owner_of(deps.as_ref(), "1"), Some(env.contract.address.clone()));
+        let err = execute(deps.as_mut(), env.clone(), mock_info(ADMIN, &[]), start).unwrap_err();
+        assert_eq!(err, ContractError::ReservedAllocationExhausted {});
+
+        let bid = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "buyer".to_string(),
+            amount: Uint128::from(PRICE),
+            msg: to_binary(&ReceiveMsg::Bid {
+                token_id: "1".to_string(),
+            })
+            .unwrap(),
+        });
+        let err = execute(deps.as_mut(), env.clone(), mock_info(TOKEN, &[]), bid).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InsufficientPayment {
+                required: Uint128::from(5 * PRICE),
+                sent: Uint128::from(PRICE),
+            }
+        );
+
+        let settle = ExecuteMsg::SettleAuction {
+            token_id: "1".to_string(),
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("buyer", &[]), settle.clone()).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::AuctionNotEnded {
+                end_time: env.block.time.plus_seconds(3600)
+            }
+        );
+        let mut ended = env;
+        ended.block.time = ended.block.time.plus_seconds(3600);
+        execute(deps.as_mut(), ended, mock_info("buyer", &[]), settle).unwrap();
+        assert_eq!(owner_of(deps.as_ref(), "1"), Some(Addr::unchecked(ADMIN)));
+        let auction: Option<Auction> = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::Auction {
+                    token_id: "1".to_string(),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(auction, None);
+    }
+
+    #[test]
+    fn auction_lots_move_like_transfers() {
+        let mut deps = setup(3, 1);
+        let env = mock_env();
+        buy(deps.as_mut(), ADMIN).unwrap();
+        let bind = ExecuteMsg::SetSoulbound {
+            token_id: "1".to_string(),
+            soulbound: Some(true),
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(ADMIN, &[]), bind).unwrap();
+        let start = |lot: AuctionLot| ExecuteMsg::StartAuction {
+            lot,
+            reserve_price: Uint128::from(PRICE),
+            min_increment: Uint128::from(10u128),
+            end_time: env.block.time.plus_seconds(3600),
+            time_extension: None,
+        };
+        let too_long = ExecuteMsg::StartAuction {
+            lot: AuctionLot::Mint { extension: None },
+            reserve_price: Uint128::from(PRICE),
+            min_increment: Uint128::from(10u128),
+            end_time: env.block.time.plus_seconds(3600),
+            time_extension: Some(u64::MAX),
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info(ADMIN, &[]), too_long).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InvalidConfig {
+                reason: "time_extension must not exceed the auction length".to_string()
+            }
+        );
+        let token_lot = AuctionLot::Token {
+            token_id: "1".to_string(),
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info(ADMIN, &[]), start(token_lot.clone())).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::Soulbound {
+                token_id: "1".to_string()
+            }
+        );
+        let unbind = ExecuteMsg::SetSoulbound {
+            token_id: "1".to_string(),
+            soulbound: None,
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(ADMIN, &[]), unbind).unwrap();
+        execute(deps.as_mut(), env.clone(), mock_info(ADMIN, &[]), start(token_lot)).unwrap();
+
+        // minted lots are delivered like a mint, even in soulbound mode
+        let soulbound = ExecuteMsg::UpdateConfig(UpdateConfigMsg {
+            soulbound: Some(true),
+            ..Default::default()
+        });
+        execute(deps.as_mut(), env.clone(), mock_info(ADMIN, &[]), soulbound).unwrap();
+        execute(deps.as_mut(), env.clone(), mock_info(ADMIN, &[]), start(AuctionLot::Mint { extension: None })).unwrap();
+
+        let pause = ExecuteMsg::SetPauseFlags {
+            buying: None,
+            metadata_updates: None,
+            transfers: Some(true),
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(ADMIN, &[]), pause).unwrap();
+        let paused = ContractError::Paused {
+            operation: "transfers".to_string(),
+        };
+        let cancel = ExecuteMsg::CancelAuction {
+            token_id: "2".to_string(),
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info(ADMIN, &[]), cancel.clone()).unwrap_err();
+        assert_eq!(err, paused);
+        let mut ended = env.clone();
+        ended.block.time = ended.block.time.plus_seconds(3600);
+        let settle = ExecuteMsg::SettleAuction {
+            token_id: "1".to_string(),
+        };
+        let err = execute(deps.as_mut(), ended.clone(), mock_info("buyer", &[]), settle.clone()).unwrap_err();
+        assert_eq!(err, paused);
+        assert!(AUCTIONS.may_load(deps.as_ref().storage, "1").unwrap().is_some());
+        let recover = ExecuteMsg::RecoverToken {
+            token_id: "3".to_string(),
+            recipient: "new_wallet".to_string(),
+        };
+        buy(deps.as_mut(), "buyer").unwrap();
+        let err = execute(deps.as_mut(), env.clone(), mock_info(ADMIN, &[]), recover).unwrap_err();
+        assert_eq!(err, paused);
+
+        let resume = ExecuteMsg::SetPauseFlags {
+            buying: None,
+            metadata_updates: None,
+            transfers: Some(false),
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(ADMIN, &[]), resume).unwrap();
+        // token 1 follows the soulbound config again
+        let err = execute(deps.as_mut(), ended.clone(), mock_info("buyer", &[]), settle).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::Soulbound {
+                token_id: "1".to_string()
+            }
+        );
+        execute(deps.as_mut(), env, mock_info(ADMIN, &[]), cancel).unwrap();
+        assert_eq!(owner_of(deps.as_ref(), "2"), Some(Addr::unchecked(ADMIN)));
+    }
+
+    #[test]
+    fn users_expire_and_are_cleared_on_transfer() {
+        let mut deps = setup(3, 0);
+        buy(deps.as_mut(), "buyer").unwrap();
+        let env = mock_env();
+        let set_user = ExecuteMsg::SetUser {
+            token_id: "1".to_string(),
+            user: Some("player".to_string()),
+            expires: Some(Expiration::AtTime(env.block.time.plus_seconds(100))),
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("player", &[]), set_user.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        execute(deps.as_mut(), env.clone(), mock_info("buyer", &[]), set_user.clone()).unwrap();
+
+        let user_of = |deps: Deps, env: Env| -> UserOfResponse {
+            from_binary(&query(deps, env, QueryMsg::UserOf { token_id: "1".to_string() }).unwrap()).unwrap()
+        };
+        assert_eq!(user_of(deps.as_ref(), env.clone()).user, Some("player".to_string()));
+        let mut later = env.clone();
+        later.block.time = later.block.time.plus_seconds(100);
+        assert_eq!(user_of(deps.as_ref(), later).user, None);
+
+        // the new owner starts without a user
+        execute(deps.as_mut(), env.clone(), mock_info("buyer", &[]), set_user).unwrap();
+        let transfer = ExecuteMsg::TransferNft {
+            recipient: "friend".to_string(),
+            token_id: "1".to_string(),
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("buyer", &[]), transfer).unwrap();
+        assert_eq!(
+            user_of(deps.as_ref(), env),
+            UserOfResponse {
+                user: None,
+                expires: None
+            }
+        );
+    }
+}
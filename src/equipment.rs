@@ -0,0 +1,165 @@
+use cosmwasm_std::{to_binary, Deps, DepsMut, Env, MessageInfo, Order, Response, StdResult, Storage, WasmMsg};
+use cw721::Cw721ExecuteMsg;
+use cw_storage_plus::Bound;
+
+use crate::errors::ContractError;
+use crate::events::{config_event, equipment_event};
+use crate::msg::{EquipmentResponse, ItemCollectionsResponse, SlotItem};
+use crate::rental::assert_not_rented;
+use crate::roles::assert_role;
+use crate::state::{EquippedItem, LootopiaNFTContract, Role, EQUIPMENT, ITEM_COLLECTIONS};
+
+pub fn set_item_collection(
+    deps: DepsMut,
+    info: MessageInfo,
+    collection: String,
+    allowed: bool,
+) -> Result<Response, ContractError> {
+    assert_role(deps.storage, &info.sender, Role::Admin)?;
+    let collection = deps.api.addr_validate(&collection)?;
+    // equipped items can still be taken off once disallowed
+    if allowed {
+        ITEM_COLLECTIONS.save(deps.storage, collection.as_bytes(), &true)?;
+    } else {
+        ITEM_COLLECTIONS.remove(deps.storage, collection.as_bytes());
+    }
+    Ok(Response::new()
+        .add_attribute("action", "set_item_collection")
+        .add_event(
+            config_event(info.sender.as_str())
+                .add_attribute("item_collection", collection)
+                .add_attribute("allowed", allowed.to_string()),
+        ))
+}
+
+// the item arrives with a SendNft on its collection, which is the sender
+pub fn equip(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    owner: String,
+    item_id: String,
+    token_id: String,
+    slot: String,
+) -> Result<Response, ContractError> {
+    let allowed = ITEM_COLLECTIONS
+        .may_load(deps.storage, info.sender.as_bytes())?
+        .unwrap_or(false);
+    if !allowed {
+        return Err(ContractError::CollectionNotAllowed {
+            collection: info.sender.to_string(),
+        });
+    }
+    let character = LootopiaNFTContract::default().tokens.load(deps.storage, &token_id)?;
+    if character.owner != owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    // the renter plays the character as it was rented
+    assert_not_rented(deps.as_ref(), &env, &token_id)?;
+    if slot.is_empty() {
+        return Err(ContractError::InvalidConfig {
+            reason: "slot must not be empty".to_string(),
+        });
+    }
+    if EQUIPMENT.may_load(deps.storage, (&token_id, &slot))?.is_some() {
+        return Err(ContractError::SlotTaken { token_id, slot });
+    }
+    EQUIPMENT.save(
+        deps.storage,
+        (&token_id, &slot),
+        &EquippedItem {
+            collection: info.sender.clone(),
+            token_id: item_id.clone(),
+        },
+    )?;
+    Ok(Response::new()
+        .add_attribute("action", "equip")
+        .add_attribute("token_id", &token_id)
+        .add_event(equipment_event("equip", &token_id, &slot, info.sender.as_str(), &item_id)))
+}
+
+// the item goes to whoever owns the character now
+pub fn unequip(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    token_id: String,
+    slot: String,
+) -> Result<Response, ContractError> {
+    let character = LootopiaNFTContract::default().tokens.load(deps.storage, &token_id)?;
+    if character.owner != info.sender {
+        return Err(ContractError::Unauthorized {});
+    }
+    assert_not_rented(deps.as_ref(), &env, &token_id)?;
+    let item = EQUIPMENT
+        .may_load(deps.storage, (&token_id, &slot))?
+        .ok_or_else(|| ContractError::SlotEmpty {
+            token_id: token_id.clone(),
+            slot: slot.clone(),
+        })?;
+    EQUIPMENT.remove(deps.storage, (&token_id, &slot));
+
+    let transfer = WasmMsg::Execute {
+        contract_addr: item.collection.to_string(),
+        msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+            recipient: character.owner.to_string(),
+            token_id: item.token_id.clone(),
+        })?,
+        funds: vec![],
+    };
+    Ok(Response::new()
+        .add_attribute("action", "unequip")
+        .add_attribute("token_id", &token_id)
+        .add_message(transfer)
+        .add_event(equipment_event("unequip", &token_id, &slot, item.collection.as_str(), &item.token_id)))
+}
+
+/// Characters can't be burned with items on, they would be stuck in the contract
+pub fn assert_unequipped(storage: &dyn Storage, token_id: &str) -> Result<(), ContractError> {
+    let equipped = EQUIPMENT
+        .prefix(token_id)
+        .range(storage, None, None, Order::Ascending)
+        .next()
+        .is_some();
+    if equipped {
+        return Err(ContractError::CharacterEquipped {
+            token_id: token_id.to_string(),
+        });
+    }
+    Ok(())
+}
+
+pub fn query_equipment(deps: Deps, token_id: String) -> StdResult<EquipmentResponse> {
+    let items: StdResult<Vec<_>> = EQUIPMENT
+        .prefix(&token_id)
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| {
+            let (slot, item) = item?;
+            Ok(SlotItem {
+                slot: String::from_utf8(slot)?,
+                item,
+            })
+        })
+        .collect();
+    Ok(EquipmentResponse { items: items? })
+}
+
+pub fn query_item_collections(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<ItemCollectionsResponse> {
+    const MAX_LIMIT: u32 = 30;
+    const DEFAULT_LIMIT: u32 = 10;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+
+    let collections: Result<Vec<_>, _> = ITEM_COLLECTIONS
+        .keys(deps.storage, start, None, Order::Ascending)
+        .map(String::from_utf8)
+        .take(limit)
+        .collect();
+    Ok(ItemCollectionsResponse {
+        collections: collections?,
+    })
+}
@@ -1,4 +1,4 @@
-use cosmwasm_std::StdError;
+use cosmwasm_std::{StdError, Timestamp, Uint128};
 use cw721_base::ContractError as CW721ContractError;
 use thiserror::Error;
 
@@ -38,14 +38,130 @@ pub enum ContractError {
     #[error("This wallet can't mint because it's not whitelisted")]
     NotWhitelisted {},
 
+    #[error("There is no pending admin proposal")]
+    NoPendingProposal {},
+
+    #[error("The admin proposal has expired")]
+    ProposalExpired {},
+
+    #[error("{operation} is paused")]
+    Paused { operation: String },
+
+    #[error("Invalid config: {reason}")]
+    InvalidConfig { reason: String },
+
+    #[error("Insufficient sales balance: {available} available, {requested} requested")]
+    InsufficientSalesBalance {
+        available: Uint128,
+        requested: Uint128,
+    },
+
+    #[error("Refunds are disabled")]
+    RefundsDisabled {},
+
+    #[error("The refund window has closed")]
+    RefundWindowClosed {},
+
+    #[error("Insufficient refund reserve: {available} available, {requested} requested")]
+    InsufficientRefundReserve {
+        available: Uint128,
+        requested: Uint128,
+    },
+
+    #[error("Nothing to refund")]
+    NothingToRefund {},
+
+    #[error("Sold out")]
+    SoldOut {},
+
+    #[error("The reserved allocation has been minted")]
+    ReservedAllocationExhausted {},
+
+    #[error("Invalid payment token: expected {expected}, got {got}")]
+    InvalidPaymentToken { expected: String, got: String },
+
+    #[error("Insufficient payment: {required} required, {sent} sent")]
+    InsufficientPayment { required: Uint128, sent: Uint128 },
+
+    #[error("The {category} loot pool is empty")]
+    EmptyTraitPool { category: String },
+
+    #[error("The sale starts at {start}")]
+    SaleNotStarted { start: Timestamp },
+
+    #[error("Invalid contract name: expected {expected}, got {got}")]
+    InvalidContractName { expected: String, got: String },
+
+    #[error("Cannot migrate from {stored} down to {target}")]
+    CannotDowngrade { stored: String, target: String },
+
+    #[error("Invalid version: {version}")]
+    InvalidVersion { version: String },
+
+    #[error("Token {token_id} is soulbound")]
+    Soulbound { token_id: String },
+
+    #[error("Token {token_id} is locked")]
+    TokenLocked { token_id: String },
+
+    #[error("{collection} items can't be equipped")]
+    CollectionNotAllowed { collection: String },
+
+    #[error("Slot {slot} of character {token_id} is taken")]
+    SlotTaken { token_id: String, slot: String },
+
+    #[error("Nothing equipped in slot {slot} of character {token_id}")]
+    SlotEmpty { token_id: String, slot: String },
+
+    #[error("Character {token_id} still has items equipped")]
+    CharacterEquipped { token_id: String },
+
+    #[error("Fusion is disabled")]
+    FusionDisabled {},
+
+    #[error("Can't fuse a token with itself")]
+    SelfFusion {},
+
+    #[error("Token {token_id} is not listed")]
+    NotListed { token_id: String },
+
+    #[error("Offer {offer_id} not found")]
+    OfferNotFound { offer_id: u64 },
+
+    #[error("Offer {offer_id} is not for token {token_id}")]
+    OfferMismatch { offer_id: u64, token_id: String },
+
+    #[error("No auction for token {token_id}")]
+    AuctionNotFound { token_id: String },
+
+    #[error("The auction has ended")]
+    AuctionEnded {},
+
+    #[error("The auction ends at {end_time}")]
+    AuctionNotEnded { end_time: Timestamp },
+
+    #[error("The auction already has bids")]
+    AuctionHasBids {},
+
+    #[error("Token {token_id} is not for rent")]
+    NotForRent { token_id: String },
+
+    #[error("Token {token_id} is rented")]
+    TokenRented { token_id: String },
+
 }
 
+// the match is exhaustive on purpose, so new cw721-base errors have to be mapped here
 impl From<CW721ContractError> for ContractError {
     fn from(msg: CW721ContractError) -> ContractError {
         match msg {
             CW721ContractError::Unauthorized {} => ContractError::Unauthorized {},
             CW721ContractError::Claimed {} => ContractError::Claimed {},
             CW721ContractError::Expired {} => ContractError::Expired {},
+            // cw721-base loads tokens with `tokens.load`, surfacing a generic not found
+            CW721ContractError::Std(StdError::NotFound { kind }) if kind.contains("TokenInfo") => {
+                ContractError::TokenNotFound {}
+            }
             CW721ContractError::Std(e) => ContractError::Std(e),
         }
     }
@@ -0,0 +1,230 @@
+//! Schema of the events emitted by the contract. The chain prefixes the
+//! types with `wasm-`, so indexers see e.g. `wasm-lootopia_buy`.
+use cosmwasm_std::{Event, Uint128};
+
+use crate::state::{Progression, Role, Trait};
+
+pub const BUY_EVENT: &str = "lootopia_buy";
+pub const CONFIG_EVENT: &str = "lootopia_config";
+pub const ADMIN_EVENT: &str = "lootopia_admin";
+pub const ROLE_EVENT: &str = "lootopia_role";
+pub const WHITELIST_EVENT: &str = "lootopia_whitelist";
+pub const PAUSE_EVENT: &str = "lootopia_pause";
+pub const METADATA_EVENT: &str = "lootopia_metadata";
+pub const WITHDRAW_EVENT: &str = "lootopia_withdraw";
+pub const REFUND_EVENT: &str = "lootopia_refund";
+pub const SOULBOUND_EVENT: &str = "lootopia_soulbound";
+pub const LOCK_EVENT: &str = "lootopia_lock";
+pub const PROGRESSION_EVENT: &str = "lootopia_progression";
+pub const EQUIPMENT_EVENT: &str = "lootopia_equipment";
+pub const FUSION_EVENT: &str = "lootopia_fusion";
+pub const MARKET_EVENT: &str = "lootopia_market";
+pub const AUCTION_EVENT: &str = "lootopia_auction";
+pub const RENTAL_EVENT: &str = "lootopia_rental";
+
+pub const ACTION: &str = "action";
+pub const BUYER: &str = "buyer";
+pub const TOKEN_ID: &str = "token_id";
+pub const PRICE: &str = "price";
+pub const PAYMENT_TOKEN: &str = "payment_token";
+pub const ADDRESS: &str = "address";
+pub const ROLE: &str = "role";
+pub const SENDER: &str = "sender";
+pub const AMOUNT: &str = "amount";
+pub const RECIPIENT: &str = "recipient";
+pub const OWNER: &str = "owner";
+pub const LOCKER: &str = "locker";
+pub const GAME: &str = "game";
+pub const SLOT: &str = "slot";
+pub const COLLECTION: &str = "collection";
+pub const ITEM_ID: &str = "item_id";
+pub const PARENTS: &str = "parents";
+pub const SELLER: &str = "seller";
+pub const BIDDER: &str = "bidder";
+pub const OFFER_ID: &str = "offer_id";
+pub const USER: &str = "user";
+// sales or refund_reserve
+pub const LEDGER: &str = "ledger";
+// traits are emitted as `trait_<trait type>`, e.g. `trait_profession`
+pub const TRAIT_PREFIX: &str = "trait_";
+
+pub fn trait_key(trait_type: &str) -> String {
+    format!("{}{}", TRAIT_PREFIX, trait_type.to_lowercase().replace(' ', "_"))
+}
+
+pub fn buy_event(
+    buyer: &str,
+    token_id: &str,
+    price: Uint128,
+    payment_token: &str,
+    name: &str,
+    traits: &[Trait],
+) -> Event {
+    let mut event = Event::new(BUY_EVENT)
+        .add_attribute(BUYER, buyer)
+        .add_attribute(TOKEN_ID, token_id)
+        .add_attribute(PRICE, price)
+        .add_attribute(PAYMENT_TOKEN, payment_token)
+        .add_attribute(trait_key("name"), name);
+    for t in traits {
+        event = event.add_attribute(trait_key(&t.trait_type), &t.value);
+    }
+    event
+}
+
+/// A config change, add one `old_<field>` / `new_<field>` pair per changed field
+pub fn config_event(sender: &str) -> Event {
+    Event::new(CONFIG_EVENT).add_attribute(SENDER, sender)
+}
+
+pub fn config_change(event: Event, field: &str, old: impl ToString, new: impl ToString) -> Event {
+    event
+        .add_attribute(format!("old_{}", field), old.to_string())
+        .add_attribute(format!("new_{}", field), new.to_string())
+}
+
+/// `action` is one of propose, accept or cancel, `role` admin or whitelist_admin
+pub fn admin_event(action: &str, role: &str, address: Option<&str>) -> Event {
+    let event = Event::new(ADMIN_EVENT)
+        .add_attribute(ACTION, action)
+        .add_attribute(ROLE, role);
+    match address {
+        Some(address) => event.add_attribute(ADDRESS, address),
+        None => event,
+    }
+}
+
+pub fn role_event(action: &str, address: &str, role: Role) -> Event {
+    Event::new(ROLE_EVENT)
+        .add_attribute(ACTION, action)
+        .add_attribute(ADDRESS, address)
+        .add_attribute(ROLE, role_name(role))
+}
+
+pub fn whitelist_event(action: &str) -> Event {
+    Event::new(WHITELIST_EVENT).add_attribute(ACTION, action)
+}
+
+pub fn pause_event(sender: &str) -> Event {
+    Event::new(PAUSE_EVENT).add_attribute(SENDER, sender)
+}
+
+pub fn metadata_event(action: &str, sender: &str) -> Event {
+    Event::new(METADATA_EVENT)
+        .add_attribute(ACTION, action)
+        .add_attribute(SENDER, sender)
+}
+
+pub fn withdraw_event(ledger: &str, sender: &str, payment_token: &str, amount: Uint128, recipient: &str) -> Event {
+    Event::new(WITHDRAW_EVENT)
+        .add_attribute(LEDGER, ledger)
+        .add_attribute(SENDER, sender)
+        .add_attribute(PAYMENT_TOKEN, payment_token)
+        .add_attribute(AMOUNT, amount)
+        .add_attribute(RECIPIENT, recipient)
+}
+
+pub fn refund_event(buyer: &str, token_id: &str, payment_token: &str, amount: Uint128) -> Event {
+    Event::new(REFUND_EVENT)
+        .add_attribute(BUYER, buyer)
+        .add_attribute(TOKEN_ID, token_id)
+        .add_attribute(PAYMENT_TOKEN, payment_token)
+        .add_attribute(AMOUNT, amount)
+}
+
+/// `action` is set_flag or recover
+pub fn soulbound_event(action: &str, sender: &str, token_id: &str) -> Event {
+    Event::new(SOULBOUND_EVENT)
+        .add_attribute(ACTION, action)
+        .add_attribute(SENDER, sender)
+        .add_attribute(TOKEN_ID, token_id)
+}
+
+/// `action` is allow_locker, revoke_locker, lock or unlock
+pub fn lock_event(action: &str, owner: &str, locker: &str) -> Event {
+    Event::new(LOCK_EVENT)
+        .add_attribute(ACTION, action)
+        .add_attribute(OWNER, owner)
+        .add_attribute(LOCKER, locker)
+}
+
+/// XP awarded by a game, with the resulting progression
+pub fn progression_event(game: &str, token_id: &str, xp_awarded: u64, progression: &Progression) -> Event {
+    Event::new(PROGRESSION_EVENT)
+        .add_attribute(GAME, game)
+        .add_attribute(TOKEN_ID, token_id)
+        .add_attribute("xp_awarded", xp_awarded.to_string())
+        .add_attribute("xp", progression.xp.to_string())
+        .add_attribute("level", progression.level.to_string())
+        .add_attribute("stat_points", progression.stat_points.to_string())
+}
+
+/// `action` is equip or unequip
+pub fn equipment_event(action: &str, token_id: &str, slot: &str, collection: &str, item_id: &str) -> Event {
+    Event::new(EQUIPMENT_EVENT)
+        .add_attribute(ACTION, action)
+        .add_attribute(TOKEN_ID, token_id)
+        .add_attribute(SLOT, slot)
+        .add_attribute(COLLECTION, collection)
+        .add_attribute(ITEM_ID, item_id)
+}
+
+/// The fused token and its traits, `parents` are comma separated
+pub fn fusion_event(owner: &str, token_id: &str, parents: &[String], name: &str, traits: &[Trait]) -> Event {
+    let mut event = Event::new(FUSION_EVENT)
+        .add_attribute(OWNER, owner)
+        .add_attribute(TOKEN_ID, token_id)
+        .add_attribute(PARENTS, parents.join(","))
+        .add_attribute(trait_key("name"), name);
+    for t in traits {
+        event = event.add_attribute(trait_key(&t.trait_type), &t.value);
+    }
+    event
+}
+
+/// `action` is list, cancel or sale
+pub fn market_event(action: &str, token_id: &str, seller: &str, payment_token: &str, price: Uint128) -> Event {
+    Event::new(MARKET_EVENT)
+        .add_attribute(ACTION, action)
+        .add_attribute(TOKEN_ID, token_id)
+        .add_attribute(SELLER, seller)
+        .add_attribute(PAYMENT_TOKEN, payment_token)
+        .add_attribute(PRICE, price)
+}
+
+/// `action` is offer, cancel_offer or accept_offer
+pub fn offer_event(action: &str, offer_id: u64, bidder: &str, payment_token: &str, price: Uint128) -> Event {
+    Event::new(MARKET_EVENT)
+        .add_attribute(ACTION, action)
+        .add_attribute(OFFER_ID, offer_id.to_string())
+        .add_attribute(BIDDER, bidder)
+        .add_attribute(PAYMENT_TOKEN, payment_token)
+        .add_attribute(PRICE, price)
+}
+
+/// `action` is start, bid, cancel or settle
+pub fn auction_event(action: &str, token_id: &str) -> Event {
+    Event::new(AUCTION_EVENT)
+        .add_attribute(ACTION, action)
+        .add_attribute(TOKEN_ID, token_id)
+}
+
+/// `action` is set_user, list, cancel or rent
+pub fn rental_event(action: &str, token_id: &str, owner: &str) -> Event {
+    Event::new(RENTAL_EVENT)
+        .add_attribute(ACTION, action)
+        .add_attribute(TOKEN_ID, token_id)
+        .add_attribute(OWNER, owner)
+}
+
+pub fn role_name(role: Role) -> &'static str {
+    match role {
+        Role::Admin => "admin",
+        Role::WhitelistManager => "whitelist_manager",
+        Role::MetadataEditor => "metadata_editor",
+        Role::Treasurer => "treasurer",
+        Role::Pauser => "pauser",
+        Role::Minter => "minter",
+        Role::Game => "game",
+    }
+}
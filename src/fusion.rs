@@ -0,0 +1,194 @@
+use cosmwasm_std::{Decimal, DepsMut, Env, MessageInfo, Response, Uint128};
+
+use crate::contract::{assert_movable, burn_token, collect_payment, get_hash, pick, CHARACTER_DESCRIPTION};
+use crate::errors::ContractError;
+use crate::events::{config_event, fusion_event};
+use crate::msg::MintMsg;
+use crate::roles::assert_role;
+use crate::traits::index_traits;
+use crate::state::{
+    FusionConfig, Loot, LootopiaNFTContract, Metadata, Role, TokenBalance, Trait, CONFIG, FUSION_CONFIG, LINEAGE,
+    LOOT,
+};
+
+// mutation rolls are drawn out of this many
+const MUTATION_SCALE: u64 = 10_000;
+
+pub fn set_fusion_config(
+    deps: DepsMut,
+    info: MessageInfo,
+    fusion_config: Option<FusionConfig>,
+) -> Result<Response, ContractError> {
+    assert_role(deps.storage, &info.sender, Role::Admin)?;
+    let mut event = config_event(info.sender.as_str());
+    match fusion_config {
+        Some(fusion_config) => {
+            if fusion_config.mutation_rate > Decimal::one() {
+                return Err(ContractError::InvalidConfig {
+                    reason: "mutation_rate must be at most 1".to_string(),
+                });
+            }
+            event = event
+                .add_attribute("fusion_fee", fusion_config.fee.unwrap_or_default())
+                .add_attribute("mutation_rate", fusion_config.mutation_rate.to_string());
+            FUSION_CONFIG.save(deps.storage, &fusion_config)?;
+        }
+        None => {
+            event = event.add_attribute("fusion", "disabled");
+            FUSION_CONFIG.remove(deps.storage);
+        }
+    }
+    Ok(Response::new()
+        .add_attribute("action", "set_fusion_config")
+        .add_event(event))
+}
+
+// trait types inherited on fusion, with the pool fresh values are rolled from
+fn trait_pools(loot: &Loot) -> [(&'static str, &'static str, &[String]); 6] {
+    [
+        ("Origin", "origins", &loot.origins),
+        ("Profession", "professions", &loot.professions),
+        ("Obsession", "obsessions", &loot.obsessions),
+        ("Talent", "talents", &loot.talents),
+        ("Skill", "skills", &loot.skills),
+        ("Alignment", "alignments", &loot.alignments),
+    ]
+}
+
+fn trait_value(metadata: &Metadata, trait_type: &str) -> Option<String> {
+    metadata
+        .attributes
+        .as_ref()?
+        .iter()
+        .find(|t| t.trait_type == trait_type)
+        .map(|t| t.value.clone())
+}
+
+// one of the parent values, or a fresh roll from the pool on a mutation or
+// when neither parent has the trait
+fn inherit(
+    parents: [Option<String>; 2],
+    pool: &[String],
+    category: &str,
+    hash: u64,
+    mutation_rate: Decimal,
+) -> Result<String, ContractError> {
+    let mutated = Uint128::from(hash % MUTATION_SCALE) < Uint128::from(MUTATION_SCALE) * mutation_rate;
+    // the rest of the hash picks the parent or the fresh value
+    let hash = hash / MUTATION_SCALE;
+    let inherited = match parents {
+        [Some(a), Some(b)] => Some(if hash & 1 == 0 { a } else { b }),
+        [Some(value), None] | [None, Some(value)] => Some(value),
+        [None, None] => None,
+    };
+    match inherited {
+        Some(value) if !mutated => Ok(value),
+        _ => Ok(pick(pool, category, hash)?.clone()),
+    }
+}
+
+/// Burns two characters of `owner` and mints the fused one to them. `payment`
+/// is what was sent with a cw20 Send, towards the fusion fee
+pub fn fuse(
+    mut deps: DepsMut,
+    env: Env,
+    owner: String,
+    token_ids: [String; 2],
+    payment: Option<TokenBalance>,
+) -> Result<Response, ContractError> {
+    let fusion_config = FUSION_CONFIG
+        .may_load(deps.storage)?
+        .ok_or(ContractError::FusionDisabled {})?;
+    let config = CONFIG.load(deps.storage)?;
+    let payment = payment.unwrap_or(TokenBalance {
+        token: config.payment_token.clone(),
+        amount: Uint128::zero(),
+    });
+    if payment.token != config.payment_token {
+        return Err(ContractError::InvalidPaymentToken {
+            expected: config.payment_token,
+            got: payment.token,
+        });
+    }
+    let fee = fusion_config.fee.unwrap_or_default();
+    if payment.amount < fee {
+        return Err(ContractError::InsufficientPayment {
+            required: fee,
+            sent: payment.amount,
+        });
+    }
+    if token_ids[0] == token_ids[1] {
+        return Err(ContractError::SelfFusion {});
+    }
+
+    let cw721_contract = LootopiaNFTContract::default();
+    let mut parents = vec![];
+    for token_id in &token_ids {
+        let token = cw721_contract.tokens.load(deps.storage, token_id)?;
+        if token.owner != owner {
+            return Err(ContractError::Unauthorized {});
+        }
+        assert_movable(deps.storage, &env, token_id)?;
+        parents.push(token.extension.unwrap_or_default());
+    }
+    for token_id in &token_ids {
+        burn_token(deps.storage, token_id)?;
+    }
+
+    let mut loot = LOOT.load(deps.storage)?;
+    let seed = (owner.as_str(), env.block.height, &token_ids);
+    let name = inherit(
+        [parents[0].name.clone(), parents[1].name.clone()],
+        &loot.names,
+        "names",
+        get_hash(&(seed, "names")),
+        fusion_config.mutation_rate,
+    )?;
+    let mut traits = vec![];
+    for (trait_type, category, pool) in trait_pools(&loot) {
+        let value = inherit(
+            [trait_value(&parents[0], trait_type), trait_value(&parents[1], trait_type)],
+            pool,
+            category,
+            get_hash(&(seed, category)),
+            fusion_config.mutation_rate,
+        )?;
+        traits.push(Trait {
+            display_type: None,
+            trait_type: trait_type.to_string(),
+            value,
+        });
+    }
+
+    // fused tokens take the next id, outside of the supply cap
+    loot.curr_num_items += 1;
+    loot.fused += 1;
+    LOOT.save(deps.storage, &loot)?;
+    let token_id = loot.curr_num_items.to_string();
+    let parent_ids = token_ids.to_vec();
+    LINEAGE.save(deps.storage, &token_id, &parent_ids)?;
+
+    let event = fusion_event(&owner, &token_id, &parent_ids, &name, &traits);
+    let payment = collect_payment(deps.branch(), &config, &payment.token, payment.amount)?;
+    let mint_msg = MintMsg {
+        token_id: token_id.clone(),
+        owner,
+        token_uri: Some(token_id),
+        extension: Some(Metadata {
+            name: Some(name),
+            description: Some(CHARACTER_DESCRIPTION.to_string()),
+            attributes: Some(traits),
+            ..Metadata::default()
+        }),
+    };
+    index_traits(deps.storage, &mint_msg.token_id, &mint_msg.extension)?;
+    let info = MessageInfo {
+        sender: cw721_contract.minter.load(deps.storage)?,
+        funds: vec![],
+    };
+    let response = cw721_contract.mint(deps, env, info, mint_msg)?;
+    Ok(response
+        .add_attribute("action", "fuse")
+        .add_messages(payment)
+        .add_event(event))
+}
@@ -1,4 +1,15 @@
 pub mod contract;
 pub mod msg;
 pub mod state;
-pub mod errors;
\ No newline at end of file
+pub mod errors;
+pub mod svg;
+pub mod roles;
+pub mod events;
+pub mod migrations;
+pub mod progression;
+pub mod equipment;
+pub mod fusion;
+pub mod traits;
+pub mod market;
+pub mod auction;
+pub mod rental;
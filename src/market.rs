@@ -0,0 +1,425 @@
+use std::convert::TryInto;
+
+use cosmwasm_std::{
+    Addr, CosmosMsg, Decimal, Deps, DepsMut, Env, MessageInfo, Order, Response, StdError, StdResult, Storage,
+    Uint128,
+};
+use cw721::Expiration;
+use cw_storage_plus::{Bound, U64Key};
+use terraswap::asset::{Asset, AssetInfo};
+
+use crate::contract::{assert_movable, collect_payment, is_soulbound, move_token};
+use crate::errors::ContractError;
+use crate::events::{config_event, market_event, offer_event, BUYER, SELLER, TOKEN_ID};
+use crate::msg::{ListingInfo, ListingsResponse, OffersResponse};
+use crate::roles::assert_role;
+use crate::state::{
+    Listing, LootopiaNFTContract, MarketConfig, Offer, OfferTarget, Role, TokenBalance, CONFIG, LISTINGS,
+    MARKET_CONFIG, OFFERS, OFFERS_BY_BIDDER, OFFERS_BY_TOKEN, OFFERS_BY_TRAIT, OFFER_COUNT, TRAIT_INDEX,
+};
+
+pub fn set_market_config(
+    deps: DepsMut,
+    info: MessageInfo,
+    market_config: MarketConfig,
+) -> Result<Response, ContractError> {
+    assert_role(deps.storage, &info.sender, Role::Admin)?;
+    if market_config.royalty_rate + market_config.fee_rate > Decimal::one() {
+        return Err(ContractError::InvalidConfig {
+            reason: "royalty_rate and fee_rate must add up to at most 1".to_string(),
+        });
+    }
+    if let Some(recipient) = &market_config.royalty_recipient {
+        deps.api.addr_validate(recipient)?;
+    }
+    MARKET_CONFIG.save(deps.storage, &market_config)?;
+    Ok(Response::new()
+        .add_attribute("action", "set_market_config")
+        .add_event(
+            config_event(info.sender.as_str())
+                .add_attribute("royalty_rate", market_config.royalty_rate.to_string())
+                .add_attribute(
+                    "royalty_recipient",
+                    market_config.royalty_recipient.as_deref().unwrap_or("treasury"),
+                )
+                .add_attribute("fee_rate", market_config.fee_rate.to_string()),
+        ))
+}
+
+pub fn list_token(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    token_id: String,
+    price: Uint128,
+    expires: Option<Expiration>,
+) -> Result<Response, ContractError> {
+    let token = LootopiaNFTContract::default().tokens.load(deps.storage, &token_id)?;
+    if token.owner != info.sender {
+        return Err(ContractError::Unauthorized {});
+    }
+    if is_soulbound(deps.storage, &token_id)? {
+        return Err(ContractError::Soulbound { token_id });
+    }
+    let expires = expires.unwrap_or_default();
+    if expires.is_expired(&env.block) {
+        return Err(ContractError::Expired {});
+    }
+    let payment_token = CONFIG.load(deps.storage)?.payment_token;
+    let listing = Listing {
+        seller: info.sender,
+        price: TokenBalance {
+            token: payment_token,
+            amount: price,
+        },
+        expires,
+    };
+    LISTINGS.save(deps.storage, &token_id, &listing)?;
+    Ok(Response::new()
+        .add_attribute("action", "list_token")
+        .add_attribute("token_id", &token_id)
+        .add_event(
+            market_event("list", &token_id, listing.seller.as_str(), &listing.price.token, price)
+                .add_attribute("expires", expires.to_string()),
+        ))
+}
+
+pub fn cancel_listing(deps: DepsMut, info: MessageInfo, token_id: String) -> Result<Response, ContractError> {
+    let listing = LISTINGS
+        .may_load(deps.storage, &token_id)?
+        .ok_or_else(|| ContractError::NotListed {
+            token_id: token_id.clone(),
+        })?;
+    if listing.seller != info.sender {
+        return Err(ContractError::Unauthorized {});
+    }
+    LISTINGS.remove(deps.storage, &token_id);
+    Ok(Response::new()
+        .add_attribute("action", "cancel_listing")
+        .add_attribute("token_id", &token_id)
+        .add_event(market_event(
+            "cancel",
+            &token_id,
+            listing.seller.as_str(),
+            &listing.price.token,
+            listing.price.amount,
+        )))
+}
+
+/// Splits `payment` between the royalty recipient, the fee and `seller`
+pub fn pay_out(
+    mut deps: DepsMut,
+    payment: &TokenBalance,
+    seller: Addr,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let market_config = MARKET_CONFIG.may_load(deps.storage)?.unwrap_or_default();
+    let royalty = payment.amount * market_config.royalty_rate;
+    let fee = payment.amount * market_config.fee_rate;
+    let proceeds = payment.amount - royalty - fee;
+
+    let royalty_recipient = match &market_config.royalty_recipient {
+        Some(recipient) => recipient.clone(),
+        None => config.treasury.clone(),
+    };
+    let mut response = Response::new()
+        .add_attribute("royalty", royalty)
+        .add_attribute("fee", fee)
+        .add_messages(collect_payment(deps.branch(), &config, &payment.token, fee)?);
+    for (recipient, amount) in [(deps.api.addr_validate(&royalty_recipient)?, royalty), (seller, proceeds)] {
+        if amount.is_zero() {
+            continue;
+        }
+        let to_pay = Asset {
+            info: AssetInfo::Token {
+                contract_addr: payment.token.clone(),
+            },
+            amount,
+        };
+        response = response.add_message(to_pay.into_msg(&deps.querier, recipient)?);
+    }
+    Ok(response)
+}
+
+pub fn buy_listing(
+    mut deps: DepsMut,
+    env: Env,
+    buyer: String,
+    token_id: String,
+    payment: TokenBalance,
+) -> Result<Response, ContractError> {
+    let listing = LISTINGS
+        .may_load(deps.storage, &token_id)?
+        .ok_or_else(|| ContractError::NotListed {
+            token_id: token_id.clone(),
+        })?;
+    if listing.expires.is_expired(&env.block) {
+        return Err(ContractError::Expired {});
+    }
+    if payment.token != listing.price.token {
+        return Err(ContractError::InvalidPaymentToken {
+            expected: listing.price.token,
+            got: payment.token,
+        });
+    }
+    if payment.amount < listing.price.amount {
+        return Err(ContractError::InsufficientPayment {
+            required: listing.price.amount,
+            sent: payment.amount,
+        });
+    }
+    // the token may have become soulbound or locked since it was listed
+    assert_movable(deps.storage, &env, &token_id)?;
+
+    // listings are removed on transfer, this is only a safeguard
+    let token = LootopiaNFTContract::default().tokens.load(deps.storage, &token_id)?;
+    if token.owner != listing.seller {
+        return Err(ContractError::NotListed { token_id });
+    }
+    let buyer = deps.api.addr_validate(&buyer)?;
+    move_token(deps.storage, &token_id, &buyer)?;
+    let response = pay_out(deps.branch(), &payment, listing.seller.clone())?;
+    Ok(response
+        .add_attribute("action", "buy_listing")
+        .add_attribute("token_id", &token_id)
+        .add_event(
+            market_event("sale", &token_id, listing.seller.as_str(), &payment.token, payment.amount)
+                .add_attribute(BUYER, buyer),
+        ))
+}
+
+pub fn query_listings(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<ListingsResponse> {
+    const MAX_LIMIT: u32 = 30;
+    const DEFAULT_LIMIT: u32 = 10;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+
+    let listings: StdResult<Vec<_>> = LISTINGS
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (token_id, listing) = item?;
+            Ok(ListingInfo {
+                token_id: String::from_utf8(token_id)?,
+                listing,
+            })
+        })
+        .collect();
+    Ok(ListingsResponse { listings: listings? })
+}
+
+fn refund_msg(deps: Deps, offer: &Offer) -> StdResult<CosmosMsg> {
+    let refund = Asset {
+        info: AssetInfo::Token {
+            contract_addr: offer.price.token.clone(),
+        },
+        amount: offer.price.amount,
+    };
+    refund.into_msg(&deps.querier, offer.bidder.clone())
+}
+
+fn remove_offer(storage: &mut dyn Storage, offer: &Offer) {
+    OFFERS.remove(storage, U64Key::from(offer.id));
+    OFFERS_BY_BIDDER.remove(storage, (offer.bidder.as_bytes(), U64Key::from(offer.id)));
+    match &offer.target {
+        OfferTarget::Token { token_id } => OFFERS_BY_TOKEN.remove(storage, (token_id, U64Key::from(offer.id))),
+        OfferTarget::Trait { trait_type, value } => {
+            OFFERS_BY_TRAIT.remove(storage, (trait_type, value, U64Key::from(offer.id)))
+        }
+    }
+}
+
+// the amount sent is held until the offer is accepted or cancelled
+pub fn make_offer(
+    deps: DepsMut,
+    env: Env,
+    bidder: String,
+    target: OfferTarget,
+    expires: Option<Expiration>,
+    payment: TokenBalance,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if payment.token != config.payment_token {
+        return Err(ContractError::InvalidPaymentToken {
+            expected: config.payment_token,
+            got: payment.token,
+        });
+    }
+    let expires = expires.unwrap_or_default();
+    if expires.is_expired(&env.block) {
+        return Err(ContractError::Expired {});
+    }
+    let bidder = deps.api.addr_validate(&bidder)?;
+    let id = OFFER_COUNT.may_load(deps.storage)?.unwrap_or_default() + 1;
+    OFFER_COUNT.save(deps.storage, &id)?;
+    match &target {
+        OfferTarget::Token { token_id } => {
+            if LootopiaNFTContract::default().tokens.may_load(deps.storage, token_id)?.is_none() {
+                return Err(ContractError::TokenNotFound {});
+            }
+            OFFERS_BY_TOKEN.save(deps.storage, (token_id, U64Key::from(id)), &true)?
+        }
+        OfferTarget::Trait { trait_type, value } => {
+            OFFERS_BY_TRAIT.save(deps.storage, (trait_type, value, U64Key::from(id)), &true)?
+        }
+    }
+    OFFERS_BY_BIDDER.save(deps.storage, (bidder.as_bytes(), U64Key::from(id)), &true)?;
+    let offer = Offer {
+        id,
+        bidder,
+        price: payment,
+        target,
+        expires,
+    };
+    OFFERS.save(deps.storage, U64Key::from(id), &offer)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "make_offer")
+        .add_attribute("offer_id", id.to_string())
+        .add_event(
+            offer_event("offer", id, offer.bidder.as_str(), &offer.price.token, offer.price.amount)
+                .add_attribute("expires", expires.to_string()),
+        ))
+}
+
+fn load_offer(storage: &dyn Storage, offer_id: u64) -> Result<Offer, ContractError> {
+    OFFERS
+        .may_load(storage, U64Key::from(offer_id))?
+        .ok_or(ContractError::OfferNotFound { offer_id })
+}
+
+// expired offers are cancelled the same way, to get the price back
+pub fn cancel_offer(deps: DepsMut, info: MessageInfo, offer_id: u64) -> Result<Response, ContractError> {
+    let offer = load_offer(deps.storage, offer_id)?;
+    if offer.bidder != info.sender {
+        return Err(ContractError::Unauthorized {});
+    }
+    remove_offer(deps.storage, &offer);
+    Ok(Response::new()
+        .add_attribute("action", "cancel_offer")
+        .add_attribute("offer_id", offer_id.to_string())
+        .add_message(refund_msg(deps.as_ref(), &offer)?)
+        .add_event(offer_event(
+            "cancel_offer",
+            offer_id,
+            offer.bidder.as_str(),
+            &offer.price.token,
+            offer.price.amount,
+        )))
+}
+
+pub fn accept_offer(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    offer_id: u64,
+    token_id: String,
+) -> Result<Response, ContractError> {
+    let offer = load_offer(deps.storage, offer_id)?;
+    if offer.expires.is_expired(&env.block) {
+        return Err(ContractError::Expired {});
+    }
+    let token = LootopiaNFTContract::default().tokens.load(deps.storage, &token_id)?;
+    if token.owner != info.sender {
+        return Err(ContractError::Unauthorized {});
+    }
+    let matches = match &offer.target {
+        OfferTarget::Token { token_id: target } => *target == token_id,
+        OfferTarget::Trait { trait_type, value } => TRAIT_INDEX
+            .may_load(deps.storage, (trait_type, value, &token_id))?
+            .is_some(),
+    };
+    if !matches {
+        return Err(ContractError::OfferMismatch { offer_id, token_id });
+    }
+    assert_movable(deps.storage, &env, &token_id)?;
+
+    remove_offer(deps.storage, &offer);
+    move_token(deps.storage, &token_id, &offer.bidder)?;
+    let response = pay_out(deps.branch(), &offer.price, info.sender.clone())?;
+    Ok(response
+        .add_attribute("action", "accept_offer")
+        .add_attribute("offer_id", offer_id.to_string())
+        .add_event(
+            offer_event(
+                "accept_offer",
+                offer_id,
+                offer.bidder.as_str(),
+                &offer.price.token,
+                offer.price.amount,
+            )
+            .add_attribute(TOKEN_ID, token_id)
+            .add_attribute(SELLER, info.sender),
+        ))
+}
+
+pub fn query_offer(deps: Deps, offer_id: u64) -> StdResult<Option<Offer>> {
+    OFFERS.may_load(deps.storage, U64Key::from(offer_id))
+}
+
+// loads the offers of an index page, whose keys end with the offer id
+fn offers_page(
+    deps: Deps,
+    ids: impl Iterator<Item = Vec<u8>>,
+    limit: Option<u32>,
+) -> StdResult<OffersResponse> {
+    const MAX_LIMIT: u32 = 30;
+    const DEFAULT_LIMIT: u32 = 10;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let offers: StdResult<Vec<_>> = ids
+        .take(limit)
+        .map(|key| {
+            let id: [u8; 8] = key
+                .as_slice()
+                .try_into()
+                .map_err(|_| StdError::generic_err("invalid offer id"))?;
+            OFFERS.load(deps.storage, U64Key::from(u64::from_be_bytes(id)))
+        })
+        .collect();
+    Ok(OffersResponse { offers: offers? })
+}
+
+pub fn query_offers_for_token(
+    deps: Deps,
+    token_id: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<OffersResponse> {
+    let start = start_after.map(Bound::exclusive_int);
+    let ids = OFFERS_BY_TOKEN
+        .prefix(&token_id)
+        .keys(deps.storage, start, None, Order::Ascending);
+    offers_page(deps, ids, limit)
+}
+
+pub fn query_offers_for_trait(
+    deps: Deps,
+    trait_type: String,
+    value: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<OffersResponse> {
+    let start = start_after.map(Bound::exclusive_int);
+    let ids = OFFERS_BY_TRAIT
+        .prefix((&trait_type, &value))
+        .keys(deps.storage, start, None, Order::Ascending);
+    offers_page(deps, ids, limit)
+}
+
+pub fn query_offers_by_bidder(
+    deps: Deps,
+    bidder: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<OffersResponse> {
+    let bidder = deps.api.addr_validate(&bidder)?;
+    let start = start_after.map(Bound::exclusive_int);
+    let ids = OFFERS_BY_BIDDER
+        .prefix(bidder.as_bytes())
+        .keys(deps.storage, start, None, Order::Ascending);
+    offers_page(deps, ids, limit)
+}
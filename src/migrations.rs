@@ -0,0 +1,85 @@
+use cosmwasm_std::{Addr, DepsMut, Response};
+use cw2::{get_contract_version, set_contract_version};
+use semver::Version;
+
+use crate::errors::ContractError;
+use crate::roles::grant_role;
+use crate::state::{Role, CONFIG, SVG_STYLE};
+
+pub const CONTRACT_NAME: &str = "crates.io:cw721-ext";
+pub const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// deployments before 0.2.0 kept the name set by cw721-base, with whatever
+// version string was passed to migrate
+const LEGACY_CONTRACT_NAME: &str = "crates.io:cw721-base";
+const LEGACY_VERSION: &str = "0.1.0";
+
+type MigrationStep = fn(&mut DepsMut) -> Result<(), ContractError>;
+
+/// Migration steps keyed by the version they upgrade from, in order. Every
+/// step from the stored version up to the current one is applied.
+/// 0.2.0 had no trait index nor trait counts, too many tokens to backfill in
+/// one transaction: the admin runs `Reindex` over the tokens after migrating.
+const MIGRATIONS: &[(&str, MigrationStep)] = &[("0.1.0", from_0_1_0)];
+
+fn parse_version(version: &str) -> Result<Version, ContractError> {
+    Version::parse(version).map_err(|_| ContractError::InvalidVersion {
+        version: version.to_string(),
+    })
+}
+
+pub fn migrate(mut deps: DepsMut) -> Result<Response, ContractError> {
+    let stored = get_contract_version(deps.storage)?;
+    let from = match stored.contract.as_str() {
+        CONTRACT_NAME => parse_version(&stored.version)?,
+        LEGACY_CONTRACT_NAME => parse_version(LEGACY_VERSION)?,
+        _ => {
+            return Err(ContractError::InvalidContractName {
+                expected: CONTRACT_NAME.to_string(),
+                got: stored.contract,
+            })
+        }
+    };
+    let to = parse_version(CONTRACT_VERSION)?;
+    if from > to {
+        return Err(ContractError::CannotDowngrade {
+            stored: from.to_string(),
+            target: to.to_string(),
+        });
+    }
+
+    let mut applied = vec![];
+    for (step_from, step) in MIGRATIONS {
+        let step_version = parse_version(step_from)?;
+        if step_version >= from && step_version < to {
+            step(&mut deps)?;
+            applied.push(*step_from);
+        }
+    }
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    let steps = if applied.is_empty() {
+        "none".to_string()
+    } else {
+        applied.join(",")
+    };
+    Ok(Response::new()
+        .add_attribute("action", "migrate")
+        .add_attribute("from_version", from.to_string())
+        .add_attribute("to_version", to.to_string())
+        .add_attribute("steps", steps))
+}
+
+// 0.1.0 had no role registry and a smaller config
+fn from_0_1_0(deps: &mut DepsMut) -> Result<(), ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    config.free_mint = config.price.is_zero();
+    CONFIG.save(deps.storage, &config)?;
+
+    grant_role(deps.storage, &Addr::unchecked(&config.admin), Role::Admin)?;
+    grant_role(deps.storage, &Addr::unchecked(&config.whitelist_admin), Role::WhitelistManager)?;
+    if SVG_STYLE.may_load(deps.storage)?.is_none() {
+        SVG_STYLE.save(deps.storage, &Default::default())?;
+    }
+    Ok(())
+}
@@ -1,9 +1,10 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::{Binary, Uint128};
+use cosmwasm_std::{Binary, Decimal, Timestamp, Uint128};
 use cw721::Expiration;
 use cw20::{Cw20ReceiveMsg};
+use cw721::Cw721ReceiveMsg;
 use cw721_base::{
     msg::{
         ExecuteMsg as CW721ExecuteMsg, InstantiateMsg as CW721InstantiateMsg,
@@ -11,7 +12,7 @@ use cw721_base::{
     },
     MintMsg as CW721MintMsg,
 };
-use crate::state::Extension;
+use crate::state::{AdminProposal, Auction, EquippedItem, Extension, FusionConfig, LevelConfig, Listing, MarketConfig, Offer, OfferTarget, RefundConfig, Role, SvgStyle, TokenBalance};
 
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -43,13 +44,30 @@ pub struct InstantiateMsg {
     pub talents: Vec<String>,
     pub skills: Vec<String>,
     pub alignments: Vec<String>,
+    // cap on the tokens minted by the sale and the reserved allocation. Fusion
+    // mints outside of it, but burns two tokens for each one it mints, so the
+    // tokens in circulation never exceed it
     pub num_items: u64,
+    // part of num_items reserved for the team, none if not set
+    pub num_reserved: Option<u64>,
     // Enable or disable whitelist
     pub whitelist: bool,
     pub whitelist_admin: String,
     
     // General admin
     pub admin: String,
+
+    // Style of the rendered character sheet, defaults if not set
+    pub svg_style: Option<SvgStyle>,
+
+    // Keep sale proceeds in the contract until withdrawn, disabled if not set
+    pub escrow: Option<bool>,
+
+    // Buying opens at this time, right away if not set
+    pub sale_start: Option<Timestamp>,
+
+    // Make all tokens non-transferable, disabled if not set
+    pub soulbound: Option<bool>,
 }
 
 impl From<InstantiateMsg> for CW721InstantiateMsg {
@@ -67,11 +85,11 @@ pub type MintMsg = CW721MintMsg<Extension>;
 // Extended CW721 ExecuteMsg, added the ability to update, burn, and finalize nft
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
 #[serde(rename_all = "snake_case")]
-
 pub enum ExecuteMsg {
 
 
-        /// Mint a new NFT, can only be called by the contract minter
+        /// Mint a new NFT from the reserved allocation, can only be called by a minter.
+        /// The token id is assigned by the contract
         Mint(MintMsg),
     
         // Standard CW721 ExecuteMsg
@@ -113,9 +131,24 @@ pub enum ExecuteMsg {
         // Receive a cw20 token message
         Receive(Cw20ReceiveMsg),
 
-        // Withdraw sales made
+        // Receive a cw721 token, sent with SendNft
+        ReceiveNft(Cw721ReceiveMsg),
+
+        // Set or disable (None) refunds of freshly bought tokens
+        SetRefundConfig {
+            refund_config: Option<RefundConfig>,
+        },
+
+        // Withdraw from the refund reserve to the treasury
+        WithdrawRefundReserve {
+            amount: Uint128,
+            payment_token: Option<String>,
+        },
+
+        // Withdraw escrowed sales to the treasury, defaults to the payment token
         WithdrawSales {
             amount: Uint128,
+            payment_token: Option<String>,
         },
 
         // create a reservation, and pay cost
@@ -127,13 +160,41 @@ pub enum ExecuteMsg {
             whitelist: bool,
         },
 
-        SetWhitelistAdmin {
-            whitelist_admin: String,
+        // Propose a new whitelist admin, who has to accept the role
+        ProposeWhitelistAdmin {
+            new_admin: String,
+            expiry: Option<Expiration>,
+        },
+        AcceptWhitelistAdmin {},
+        CancelWhitelistAdminProposal {},
+
+        // Propose a new admin, who has to accept the role
+        ProposeAdmin {
+            new_admin: String,
+            expiry: Option<Expiration>,
+        },
+        AcceptAdmin {},
+        CancelAdminProposal {},
+
+        // Grant / revoke a role to an address, admin only
+        GrantRole {
+            address: String,
+            role: Role,
         },
+        RevokeRole {
+            address: String,
+            role: Role,
+        },
+
+        // Update the sale config, fields left out are unchanged
+        UpdateConfig(UpdateConfigMsg),
 
-        // Set the admin
-        SetAdmin {
-            admin: String,
+        // Halt or resume parts of the contract, flags left out are unchanged
+        SetPauseFlags {
+            // every cw20 payment, refund reserve top-ups aside
+            buying: Option<bool>,
+            metadata_updates: Option<bool>,
+            transfers: Option<bool>,
         },
 
         // Update token
@@ -142,6 +203,169 @@ pub enum ExecuteMsg {
             extension: Extension,
         },
 
+        // Override the soulbound mode for a token, None to follow the config
+        SetSoulbound {
+            token_id: String,
+            soulbound: Option<bool>,
+        },
+
+        // Move a token regardless of soulbound mode, for lost wallets
+        RecoverToken {
+            token_id: String,
+            recipient: String,
+        },
+
+        // Allow / disallow a game contract to lock the sender's tokens
+        AllowLocker {
+            locker: String,
+        },
+        RevokeLocker {
+            locker: String,
+        },
+
+        // Lock a token while it is used in game, only by an allowed locker
+        Lock {
+            token_id: String,
+            until: Expiration,
+        },
+        // Unlock a token, only by the contract that locked it
+        Unlock {
+            token_id: String,
+        },
+
+        // Let another address use a token in game until it expires, None to
+        // clear it. Owner only, the user is cleared when the token changes hands
+        SetUser {
+            token_id: String,
+            user: Option<String>,
+            expires: Option<Expiration>,
+        },
+        // Offer a token for rent, paid with a cw20 Send of the payment token
+        ListRental {
+            token_id: String,
+            price: Uint128,
+            // seconds of use per rental
+            duration: u64,
+        },
+        CancelRental {
+            token_id: String,
+        },
+
+        // Index the traits of a page of tokens, after migrating from a
+        // version without the trait index. Admin only
+        Reindex {
+            start_after: Option<String>,
+            limit: Option<u32>,
+        },
+
+        // Update the style of the rendered character sheet
+        UpdateSvgStyle {
+            svg_style: SvgStyle,
+        },
+
+        // Award XP to a character, only by a game contract
+        AwardXp {
+            token_id: String,
+            amount: u64,
+        },
+
+        // Set the XP thresholds of the levels, admin only
+        SetLevelConfig {
+            level_config: LevelConfig,
+        },
+
+        // Allow or disallow equipping the items of a collection, admin only
+        SetItemCollection {
+            collection: String,
+            allowed: bool,
+        },
+
+        // Send an equipped item back to the owner of the character
+        Unequip {
+            token_id: String,
+            slot: String,
+        },
+
+        // Burn two of the sender's characters for a new one inheriting their traits.
+        // The new token takes the next id without counting towards num_items.
+        // With a fusion fee, fuse through a cw20 Send instead
+        Fuse {
+            token_ids: [String; 2],
+        },
+
+        // Set or disable (None) fusion, admin only
+        SetFusionConfig {
+            fusion_config: Option<FusionConfig>,
+        },
+
+        // List a token for sale in the payment token, bought with a cw20 Send.
+        // Listing again replaces the previous listing
+        ListToken {
+            token_id: String,
+            price: Uint128,
+            expires: Option<Expiration>,
+        },
+        CancelListing {
+            token_id: String,
+        },
+
+        // Take back an offer and its escrowed price
+        CancelOffer {
+            offer_id: u64,
+        },
+        // Sell a token of the sender to an offer made for it
+        AcceptOffer {
+            offer_id: u64,
+            token_id: String,
+        },
+
+        // Auction a token, bid on with cw20 Sends of the payment token. Admin only
+        StartAuction {
+            lot: AuctionLot,
+            reserve_price: Uint128,
+            min_increment: Uint128,
+            end_time: Timestamp,
+            // in seconds, at most the auction length. 10 minutes if not set, or the
+            // auction length if shorter
+            time_extension: Option<u64>,
+        },
+        // Stop an auction before any bid, the token goes back to the admin
+        CancelAuction {
+            token_id: String,
+        },
+        // Give the token to the highest bidder and the winning bid to the
+        // treasury, once the auction has ended. Anyone can settle
+        SettleAuction {
+            token_id: String,
+        },
+
+        // Set the royalty and fee taken from marketplace sales, admin only
+        SetMarketConfig {
+            market_config: MarketConfig,
+        },
+
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug, Default)]
+pub struct UpdateConfigMsg {
+    pub payment_token: Option<String>,
+    pub price: Option<Uint128>,
+    pub treasury: Option<String>,
+    pub limit_per_address: Option<u64>,
+    pub free_mint: Option<bool>,
+    // keep sale proceeds in the contract until withdrawn
+    pub escrow: Option<bool>,
+    pub sale_start: Option<Timestamp>,
+    pub soulbound: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum AuctionLot {
+    // a new token from the reserved allocation, minted to the contract
+    Mint { extension: Extension },
+    // a token of the sender, held by the contract during the auction
+    Token { token_id: String },
 }
 
 impl From<ExecuteMsg> for CW721ExecuteMsg<Extension> {
@@ -188,8 +412,44 @@ impl From<ExecuteMsg> for CW721ExecuteMsg<Extension> {
 #[serde(rename_all = "snake_case")]
 pub enum ReceiveMsg {
     Buy {
-        mint_msg: CW721MintMsg<Extension>,
-    }
+        mint_msg: Box<CW721MintMsg<Extension>>,
+    },
+    // Top up the reserve refunds are paid from
+    FundRefundReserve {},
+    // Fuse two characters, paying the fusion fee
+    Fuse {
+        token_ids: [String; 2],
+    },
+    // Buy a listed token, paying its price
+    BuyListing {
+        token_id: String,
+    },
+    // Rent a listed token, the sender becomes its user
+    Rent {
+        token_id: String,
+    },
+    // Bid the amount sent on an auction
+    Bid {
+        token_id: String,
+    },
+    // Offer the amount sent for a token, or any token with a trait
+    MakeOffer {
+        target: OfferTarget,
+        expires: Option<Expiration>,
+    },
+}
+
+// Messages accepted with a cw721 SendNft to this contract
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum NftReceiveMsg {
+    // Return a token bought within the refund window
+    Refund {},
+    // Equip the item sent to one of the sender's characters
+    Equip {
+        token_id: String,
+        slot: String,
+    },
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
@@ -227,7 +487,142 @@ pub enum QueryMsg {
     Whitelisted {
         start_after: Option<String>,
         limit: Option<u32>,
-    }
+    },
+    // Owner and metadata of each of the tokens, at most 30 at once
+    NftInfoBatch {
+        token_ids: Vec<String>,
+    },
+    // AllTokens, with the owner and metadata of each token
+    AllTokensWithInfo {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    // Tokens, with the metadata of each token
+    TokensWithInfo {
+        owner: String,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    // Render the character sheet of a token as SVG
+    CharacterSheet {
+        token_id: String,
+    },
+    // Pending handovers of the admin roles
+    AdminProposals {},
+    // Currently paused operations
+    PauseFlags {},
+    // Escrowed sale proceeds, per payment token
+    SalesBalance {},
+    // Whether a token can be transferred
+    Soulbound {
+        token_id: String,
+    },
+    // Whether a token is locked, and by whom
+    LockStatus {
+        token_id: String,
+    },
+    // Who may use a token in game, None once expired
+    UserOf {
+        token_id: String,
+    },
+    RentalListing {
+        token_id: String,
+    },
+    // Supply cap and tokens minted so far
+    SupplyInfo {},
+    // Refund settings and reserve
+    RefundInfo {},
+    // Revenue and number of sales since instantiation
+    SalesStats {},
+    // Purchases made by an address
+    BuyerStats {
+        address: String,
+    },
+    // Roles granted to an address
+    RolesOf {
+        address: String,
+    },
+    // Addresses holding a role
+    RoleMembers {
+        role: Role,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    // XP, level and stat points of a character
+    Progression {
+        token_id: String,
+    },
+    // XP thresholds of the levels
+    LevelConfig {},
+    // Items equipped by a character, by slot
+    Equipment {
+        token_id: String,
+    },
+    // Tokens with a trait, e.g. Profession = Alchemist
+    TokensByTrait {
+        trait_type: String,
+        value: String,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    // Number of tokens with each trait, by trait type and value
+    TraitDistribution {
+        // (trait_type, value) of the last trait of the previous page
+        start_after: Option<(String, String)>,
+        limit: Option<u32>,
+    },
+    // Rarity score of a token, from the frequencies of its traits
+    Rarity {
+        token_id: String,
+    },
+    // Listing of a token, none if not for sale
+    Listing {
+        token_id: String,
+    },
+    Listings {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    // Royalty and fee taken from marketplace sales
+    MarketConfig {},
+    Offer {
+        offer_id: u64,
+    },
+    Auction {
+        token_id: String,
+    },
+    Auctions {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    // Offers made for a token, not including offers for its traits
+    OffersForToken {
+        token_id: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    OffersForTrait {
+        trait_type: String,
+        value: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    OffersByBidder {
+        bidder: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    // Fusion settings, none if disabled
+    FusionConfig {},
+    // Parents of a fused token, empty for other tokens
+    Lineage {
+        token_id: String,
+    },
+    // Collections whose items can be equipped
+    ItemCollections {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
 }
 
 impl From<QueryMsg> for CW721QueryMsg {
@@ -283,9 +678,162 @@ pub struct AllWhitelisted {
     pub accounts: Vec<String>,
 }
 
+/// A token as returned by NftInfo, with its id and owner
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct TokenWithInfo {
+    pub token_id: String,
+    pub owner: String,
+    pub token_uri: Option<String>,
+    pub extension: Extension,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct TokensWithInfoResponse {
+    pub tokens: Vec<TokenWithInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct SoulboundResponse {
+    pub soulbound: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct UserOfResponse {
+    pub user: Option<String>,
+    pub expires: Option<Expiration>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct LockStatusResponse {
+    pub locked: bool,
+    pub locker: Option<String>,
+    pub until: Option<Expiration>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct SupplyInfoResponse {
+    pub num_items: u64,
+    pub minted: u64,
+    pub num_reserved: u64,
+    pub reserved_minted: u64,
+    // minted by fusion, on top of num_items. Each fusion burns two tokens,
+    // so at most minted - fused tokens are in circulation
+    pub fused: u64,
+    // refunded sale tokens, left out of minted as their slots are back on sale
+    pub refunded: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct RefundInfoResponse {
+    pub refund_config: Option<RefundConfig>,
+    pub reserve: Vec<TokenBalance>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct SalesBalanceResponse {
+    pub balances: Vec<TokenBalance>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct RolesResponse {
+    pub roles: Vec<Role>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct RoleMembersResponse {
+    pub members: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct AdminProposalsResponse {
+    pub admin: Option<AdminProposal>,
+    pub whitelist_admin: Option<AdminProposal>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct ProgressionResponse {
+    pub xp: u64,
+    pub level: u32,
+    pub stat_points: u32,
+    // XP needed for the next level, none at the max level
+    pub next_level_xp: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct TraitCount {
+    pub trait_type: String,
+    pub value: String,
+    pub count: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct TraitDistributionResponse {
+    pub num_tokens: u64,
+    pub traits: Vec<TraitCount>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct RarityResponse {
+    pub num_tokens: u64,
+    // sum of num_tokens / count over the traits of the token
+    pub score: Decimal,
+    pub traits: Vec<TraitCount>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct ListingInfo {
+    pub token_id: String,
+    pub listing: Listing,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct ListingsResponse {
+    pub listings: Vec<ListingInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct AuctionInfo {
+    pub token_id: String,
+    pub auction: Auction,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct AuctionsResponse {
+    pub auctions: Vec<AuctionInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct OffersResponse {
+    pub offers: Vec<Offer>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct LineageResponse {
+    pub parents: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct EquipmentResponse {
+    pub items: Vec<SlotItem>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct SlotItem {
+    pub slot: String,
+    pub item: EquippedItem,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct ItemCollectionsResponse {
+    pub collections: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct CharacterSheetResponse {
+    pub svg: String,
+}
+
+/// The version migrated to is the one of the code, config changes go through UpdateConfig
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
 #[serde(rename_all = "snake_case")]
-pub struct MigrateMsg<T> {
-    pub version: String,
-    pub config: Option<T>,
-}
\ No newline at end of file
+pub struct MigrateMsg {}
\ No newline at end of file
@@ -0,0 +1,94 @@
+use cosmwasm_std::{Deps, DepsMut, MessageInfo, OverflowError, OverflowOperation, Response, StdError, StdResult, Storage};
+
+use crate::errors::ContractError;
+use crate::events::{config_event, progression_event};
+use crate::msg::ProgressionResponse;
+use crate::roles::assert_role;
+use crate::state::{
+    Extension, LevelConfig, LootopiaNFTContract, Progression, Role, Trait, LEVEL_CONFIG, PROGRESSION,
+};
+
+pub const LEVEL_TRAIT: &str = "Level";
+
+fn level_for(config: &LevelConfig, xp: u64) -> u32 {
+    1 + config.thresholds.iter().filter(|threshold| xp >= **threshold).count() as u32
+}
+
+pub fn progression_of(storage: &dyn Storage, token_id: &str) -> StdResult<Progression> {
+    Ok(PROGRESSION.may_load(storage, token_id)?.unwrap_or_default())
+}
+
+pub fn award_xp(deps: DepsMut, info: MessageInfo, token_id: String, amount: u64) -> Result<Response, ContractError> {
+    assert_role(deps.storage, &info.sender, Role::Game)?;
+    if LootopiaNFTContract::default().tokens.may_load(deps.storage, &token_id)?.is_none() {
+        return Err(ContractError::TokenNotFound {});
+    }
+    let config = LEVEL_CONFIG.may_load(deps.storage)?.unwrap_or_default();
+    let mut progression = progression_of(deps.storage, &token_id)?;
+    progression.xp = progression.xp.saturating_add(amount);
+    // raising the thresholds never takes levels away
+    let level = level_for(&config, progression.xp).max(progression.level);
+    let levels_gained = level - progression.level;
+    let overflow = |operation, operand1: u32, operand2: u32| {
+        StdError::from(OverflowError::new(operation, operand1, operand2))
+    };
+    let points = levels_gained
+        .checked_mul(config.stat_points_per_level)
+        .ok_or_else(|| overflow(OverflowOperation::Mul, levels_gained, config.stat_points_per_level))?;
+    let stat_points = progression
+        .stat_points
+        .checked_add(points)
+        .ok_or_else(|| overflow(OverflowOperation::Add, progression.stat_points, points))?;
+    progression.stat_points = stat_points;
+    progression.level = level;
+    PROGRESSION.save(deps.storage, &token_id, &progression)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "award_xp")
+        .add_attribute("token_id", &token_id)
+        .add_event(progression_event(info.sender.as_str(), &token_id, amount, &progression)))
+}
+
+pub fn set_level_config(deps: DepsMut, info: MessageInfo, config: LevelConfig) -> Result<Response, ContractError> {
+    assert_role(deps.storage, &info.sender, Role::Admin)?;
+    if config.thresholds.windows(2).any(|pair| pair[0] >= pair[1]) {
+        return Err(ContractError::InvalidConfig {
+            reason: "level thresholds must be strictly ascending".to_string(),
+        });
+    }
+    LEVEL_CONFIG.save(deps.storage, &config)?;
+    Ok(Response::new()
+        .add_attribute("action", "set_level_config")
+        .add_event(
+            config_event(info.sender.as_str())
+                .add_attribute("max_level", (config.thresholds.len() + 1).to_string())
+                .add_attribute("stat_points_per_level", config.stat_points_per_level.to_string()),
+        ))
+}
+
+/// Adds the level to the metadata as a number trait, it isn't stored with the
+/// token. Tokens without metadata are left as they are
+pub fn with_level(storage: &dyn Storage, token_id: &str, extension: Extension) -> StdResult<Extension> {
+    let mut metadata = match extension {
+        Some(metadata) => metadata,
+        None => return Ok(None),
+    };
+    let level = Trait {
+        display_type: Some("number".to_string()),
+        trait_type: LEVEL_TRAIT.to_string(),
+        value: progression_of(storage, token_id)?.level.to_string(),
+    };
+    metadata.attributes.get_or_insert_with(Vec::new).push(level);
+    Ok(Some(metadata))
+}
+
+pub fn query_progression(deps: Deps, token_id: String) -> StdResult<ProgressionResponse> {
+    let progression = progression_of(deps.storage, &token_id)?;
+    let config = LEVEL_CONFIG.may_load(deps.storage)?.unwrap_or_default();
+    Ok(ProgressionResponse {
+        next_level_xp: config.thresholds.get(progression.level as usize - 1).copied(),
+        xp: progression.xp,
+        level: progression.level,
+        stat_points: progression.stat_points,
+    })
+}
@@ -0,0 +1,203 @@
+//! ERC-4907 style users: the owner lets another address use a token in game
+//! until an expiry, directly or through a paid rental. Paid rentals lock the
+//! token in the name of this contract, so it can't change hands under the
+//! renter.
+use cosmwasm_std::{Deps, DepsMut, Env, MessageInfo, Response, StdResult, Uint128};
+use cw721::Expiration;
+
+use crate::contract::{active_lock, checked_plus_seconds};
+use crate::errors::ContractError;
+use crate::events::{rental_event, PAYMENT_TOKEN, PRICE, USER};
+use crate::market::pay_out;
+use crate::msg::UserOfResponse;
+use crate::state::{
+    LootopiaNFTContract, RentalListing, TokenBalance, TokenLock, TokenUser, CONFIG, LOCKS,
+    RENTAL_LISTINGS, USERS,
+};
+
+// a year, rentals lock the token for that long
+const MAX_DURATION: u64 = 365 * 24 * 60 * 60;
+
+// the owner can't take a token back from its renter
+pub(crate) fn assert_not_rented(deps: Deps, env: &Env, token_id: &str) -> Result<(), ContractError> {
+    if let Some(lock) = active_lock(deps.storage, env, token_id)? {
+        if lock.locker == env.contract.address {
+            return Err(ContractError::TokenRented {
+                token_id: token_id.to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+pub fn set_user(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    token_id: String,
+    user: Option<String>,
+    expires: Option<Expiration>,
+) -> Result<Response, ContractError> {
+    let token = LootopiaNFTContract::default().tokens.load(deps.storage, &token_id)?;
+    if token.owner != info.sender {
+        return Err(ContractError::Unauthorized {});
+    }
+    assert_not_rented(deps.as_ref(), &env, &token_id)?;
+    let mut event = rental_event("set_user", &token_id, token.owner.as_str());
+    match user {
+        Some(user) => {
+            let expires = expires.unwrap_or_default();
+            if expires.is_expired(&env.block) {
+                return Err(ContractError::Expired {});
+            }
+            let user = deps.api.addr_validate(&user)?;
+            event = event
+                .add_attribute(USER, &user)
+                .add_attribute("expires", expires.to_string());
+            USERS.save(deps.storage, &token_id, &TokenUser { user, expires })?;
+        }
+        None => USERS.remove(deps.storage, &token_id),
+    }
+    Ok(Response::new()
+        .add_attribute("action", "set_user")
+        .add_attribute("token_id", &token_id)
+        .add_event(event))
+}
+
+pub fn list_rental(
+    deps: DepsMut,
+    info: MessageInfo,
+    token_id: String,
+    price: Uint128,
+    duration: u64,
+) -> Result<Response, ContractError> {
+    let token = LootopiaNFTContract::default().tokens.load(deps.storage, &token_id)?;
+    if token.owner != info.sender {
+        return Err(ContractError::Unauthorized {});
+    }
+    if duration == 0 {
+        return Err(ContractError::InvalidConfig {
+            reason: "duration must be positive".to_string(),
+        });
+    }
+    if duration > MAX_DURATION {
+        return Err(ContractError::InvalidConfig {
+            reason: format!("duration must be at most {} seconds", MAX_DURATION),
+        });
+    }
+    let payment_token = CONFIG.load(deps.storage)?.payment_token;
+    let listing = RentalListing {
+        owner: info.sender,
+        price: TokenBalance {
+            token: payment_token,
+            amount: price,
+        },
+        duration,
+    };
+    RENTAL_LISTINGS.save(deps.storage, &token_id, &listing)?;
+    Ok(Response::new()
+        .add_attribute("action", "list_rental")
+        .add_attribute("token_id", &token_id)
+        .add_event(
+            rental_event("list", &token_id, listing.owner.as_str())
+                .add_attribute(PAYMENT_TOKEN, &listing.price.token)
+                .add_attribute(PRICE, price)
+                .add_attribute("duration", duration.to_string()),
+        ))
+}
+
+// a running rental is not affected
+pub fn cancel_rental(deps: DepsMut, info: MessageInfo, token_id: String) -> Result<Response, ContractError> {
+    let listing = RENTAL_LISTINGS
+        .may_load(deps.storage, &token_id)?
+        .ok_or_else(|| ContractError::NotForRent {
+            token_id: token_id.clone(),
+        })?;
+    if listing.owner != info.sender {
+        return Err(ContractError::Unauthorized {});
+    }
+    RENTAL_LISTINGS.remove(deps.storage, &token_id);
+    Ok(Response::new()
+        .add_attribute("action", "cancel_rental")
+        .add_attribute("token_id", &token_id)
+        .add_event(rental_event("cancel", &token_id, listing.owner.as_str())))
+}
+
+/// Makes `renter` the user of a listed token for the listing duration, the
+/// payment is split like a marketplace sale. The listing stays up for the
+/// next rental once this one ends
+pub fn rent(
+    mut deps: DepsMut,
+    env: Env,
+    renter: String,
+    token_id: String,
+    payment: TokenBalance,
+) -> Result<Response, ContractError> {
+    let listing = RENTAL_LISTINGS
+        .may_load(deps.storage, &token_id)?
+        .ok_or_else(|| ContractError::NotForRent {
+            token_id: token_id.clone(),
+        })?;
+    if payment.token != listing.price.token {
+        return Err(ContractError::InvalidPaymentToken {
+            expected: listing.price.token,
+            got: payment.token,
+        });
+    }
+    if payment.amount < listing.price.amount {
+        return Err(ContractError::InsufficientPayment {
+            required: listing.price.amount,
+            sent: payment.amount,
+        });
+    }
+    assert_not_rented(deps.as_ref(), &env, &token_id)?;
+    // nor while a game holds it
+    if active_lock(deps.storage, &env, &token_id)?.is_some() {
+        return Err(ContractError::TokenLocked { token_id });
+    }
+    // rental listings are removed on transfer, this is only a safeguard
+    let token = LootopiaNFTContract::default().tokens.load(deps.storage, &token_id)?;
+    if token.owner != listing.owner {
+        return Err(ContractError::NotForRent { token_id });
+    }
+
+    let renter = deps.api.addr_validate(&renter)?;
+    let expires = Expiration::AtTime(checked_plus_seconds(env.block.time, listing.duration)?);
+    USERS.save(
+        deps.storage,
+        &token_id,
+        &TokenUser {
+            user: renter.clone(),
+            expires,
+        },
+    )?;
+    LOCKS.save(
+        deps.storage,
+        &token_id,
+        &TokenLock {
+            locker: env.contract.address.clone(),
+            until: expires,
+        },
+    )?;
+    let response = pay_out(deps.branch(), &payment, listing.owner.clone())?;
+    Ok(response
+        .add_attribute("action", "rent")
+        .add_attribute("token_id", &token_id)
+        .add_event(
+            rental_event("rent", &token_id, listing.owner.as_str())
+                .add_attribute(USER, renter)
+                .add_attribute(PAYMENT_TOKEN, &payment.token)
+                .add_attribute(PRICE, payment.amount)
+                .add_attribute("expires", expires.to_string()),
+        ))
+}
+
+pub fn query_user_of(deps: Deps, env: Env, token_id: String) -> StdResult<UserOfResponse> {
+    let user = USERS
+        .may_load(deps.storage, &token_id)?
+        .filter(|user| !user.expires.is_expired(&env.block));
+    Ok(UserOfResponse {
+        user: user.as_ref().map(|user| user.user.to_string()),
+        expires: user.map(|user| user.expires),
+    })
+}
@@ -0,0 +1,44 @@
+use cosmwasm_std::{Addr, StdResult, Storage};
+
+use crate::errors::ContractError;
+use crate::state::{Role, ROLES};
+
+pub fn roles_of(storage: &dyn Storage, address: &Addr) -> StdResult<Vec<Role>> {
+    Ok(ROLES
+        .may_load(storage, address.as_bytes())?
+        .unwrap_or_default())
+}
+
+/// Admins implicitly hold every role
+pub fn has_role(storage: &dyn Storage, address: &Addr, role: Role) -> StdResult<bool> {
+    let roles = roles_of(storage, address)?;
+    Ok(roles.contains(&role) || roles.contains(&Role::Admin))
+}
+
+/// The authorization check used by every handler
+pub fn assert_role(storage: &dyn Storage, address: &Addr, role: Role) -> Result<(), ContractError> {
+    if !has_role(storage, address, role)? {
+        return Err(ContractError::Unauthorized {});
+    }
+    Ok(())
+}
+
+pub fn grant_role(storage: &mut dyn Storage, address: &Addr, role: Role) -> StdResult<()> {
+    let mut roles = roles_of(storage, address)?;
+    if !roles.contains(&role) {
+        roles.push(role);
+        ROLES.save(storage, address.as_bytes(), &roles)?;
+    }
+    Ok(())
+}
+
+pub fn revoke_role(storage: &mut dyn Storage, address: &Addr, role: Role) -> StdResult<()> {
+    let mut roles = roles_of(storage, address)?;
+    roles.retain(|r| *r != role);
+    if roles.is_empty() {
+        ROLES.remove(storage, address.as_bytes());
+    } else {
+        ROLES.save(storage, address.as_bytes(), &roles)?;
+    }
+    Ok(())
+}
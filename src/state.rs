@@ -1,12 +1,18 @@
-use cosmwasm_std::{Empty, Uint128};
+use cosmwasm_std::{Addr, Decimal, Empty, Timestamp, Uint128};
+use cw721::Expiration;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use cw_storage_plus::{Item, Map};
+use cw_storage_plus::{Item, Map, U64Key};
+use cw721_base::state::TokenInfo;
 
 
 pub type LootopiaNFTContract<'a> = cw721_base::Cw721Contract<'a, Extension, Empty>;
 pub type Extension = Option<Metadata>;
 
+/// Read only view of the tokens stored by cw721-base, which is on another
+/// version of cw-storage-plus, to range over them with our bounds
+pub const TOKENS: Map<&str, TokenInfo<Extension>> = Map::new("tokens");
+
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug, Default)]
 pub struct Trait {
     pub display_type: Option<String>,
@@ -38,11 +44,33 @@ pub struct Config {
     pub whitelist: bool,
     pub whitelist_admin: String,
     pub admin: String,
+    // allows a zero price
+    #[serde(default)]
+    pub free_mint: bool,
+    // sale proceeds stay in the contract, see SALES_BALANCE
+    #[serde(default)]
+    pub escrow: bool,
+    // buying is rejected before this time
+    #[serde(default)]
+    pub sale_start: Option<Timestamp>,
+    // tokens can't be transferred or approved, unless flagged otherwise
+    #[serde(default)]
+    pub soulbound: bool,
 }
 
 
 pub const CONFIG: Item<Config> = Item::new("config");
 
+/// A pending handover of an admin role, it has to be accepted by `new_admin`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct AdminProposal {
+    pub new_admin: Addr,
+    pub expiry: Expiration,
+}
+
+pub const ADMIN_PROPOSAL: Item<AdminProposal> = Item::new("admin_proposal");
+pub const WHITELIST_ADMIN_PROPOSAL: Item<AdminProposal> = Item::new("whitelist_admin_proposal");
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct Loot {
     pub names: Vec<String>,
@@ -52,11 +80,305 @@ pub struct Loot {
     pub talents: Vec<String>,
     pub skills: Vec<String>,
     pub alignments: Vec<String>,
+    /// Hard cap on the number of tokens, reserved ones included
     pub num_items: u64,
+    /// Tokens minted so far, the last token id
     pub curr_num_items: u64,
+    /// Part of num_items kept for the team, only mintable by a minter
+    #[serde(default)]
+    pub num_reserved: u64,
+    #[serde(default)]
+    pub reserved_minted: u64,
+    /// Tokens minted by fusing two others, they don't count towards num_items
+    #[serde(default)]
+    pub fused: u64,
+    /// Sale tokens refunded and burned, their slots are back on sale
+    #[serde(default)]
+    pub refunded: u64,
+}
+
+impl Loot {
+    /// Tokens minted by the sale and from the reserved allocation, refunded ones aside
+    pub fn minted(&self) -> u64 {
+        self.curr_num_items - self.fused - self.refunded
+    }
 }
 
 pub const LOOT: Item<Loot> = Item::new("loot");
 
+/// Look of the on-chain rendered character sheet
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SvgStyle {
+    pub font_family: String,
+    pub font_size: u32,
+    pub text_color: String,
+    // used when the token metadata has no background_color
+    pub background_color: String,
+}
+
+impl Default for SvgStyle {
+    fn default() -> Self {
+        SvgStyle {
+            font_family: "serif".to_string(),
+            font_size: 14,
+            text_color: "white".to_string(),
+            background_color: "black".to_string(),
+        }
+    }
+}
+
+pub const SVG_STYLE: Item<SvgStyle> = Item::new("svg_style");
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Can do everything, including granting and revoking roles
+    Admin,
+    WhitelistManager,
+    MetadataEditor,
+    Treasurer,
+    Pauser,
+    Minter,
+    /// Game contracts awarding XP to characters
+    Game,
+}
+
+pub const ROLES: Map<&[u8], Vec<Role>> = Map::new("roles");
+
+/// What is currently halted, set by a pauser
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct PauseFlags {
+    // every cw20 payment: sales, listings, offers, rentals, bids and paid
+    // fusions. Topping up the refund reserve is not a purchase
+    pub buying: bool,
+    pub metadata_updates: bool,
+    // cw721 transfers, sends and approvals
+    pub transfers: bool,
+}
+
+pub const PAUSE_FLAGS: Item<PauseFlags> = Item::new("pause_flags");
+
+/// An amount of a cw20 token
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TokenBalance {
+    pub token: String,
+    pub amount: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct SalesStats {
+    pub num_sales: u64,
+    pub unique_buyers: u64,
+    // one entry per payment token ever used
+    pub revenue: Vec<TokenBalance>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct BuyerStats {
+    pub num_purchases: u64,
+    pub spent: Vec<TokenBalance>,
+}
+
+pub const SALES_STATS: Item<SalesStats> = Item::new("sales_stats");
+pub const BUYER_STATS: Map<&[u8], BuyerStats> = Map::new("buyer_stats");
+
+/// Escrowed sale proceeds not yet withdrawn, by payment token address
+pub const SALES_BALANCE: Map<&[u8], Uint128> = Map::new("sales_balance");
+
+/// Buyers can send a token back within `window_blocks` of its purchase
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RefundConfig {
+    pub window_blocks: u64,
+    // share of the purchase price refunded, 1 for a full refund
+    pub refund_rate: Decimal,
+}
+
+pub const REFUND_CONFIG: Item<RefundConfig> = Item::new("refund_config");
+
+/// Funds refunds are paid from, by payment token address
+pub const REFUND_RESERVE: Map<&[u8], Uint128> = Map::new("refund_reserve");
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Purchase {
+    pub buyer: Addr,
+    pub price: TokenBalance,
+    pub height: u64,
+}
+
+/// Purchase of each token bought through the sale, by token id
+pub const PURCHASES: Map<&str, Purchase> = Map::new("purchases");
+
+/// Per token soulbound flags, overriding Config.soulbound
+pub const SOULBOUND_TOKENS: Map<&str, bool> = Map::new("soulbound_tokens");
+
+/// A token locked by a game contract, it can't be transferred until unlocked
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TokenLock {
+    pub locker: Addr,
+    pub until: Expiration,
+}
+
+pub const LOCKS: Map<&str, TokenLock> = Map::new("locks");
+/// Contracts allowed by an owner to lock their tokens, keyed by (owner, locker)
+pub const LOCKERS: Map<(&[u8], &[u8]), bool> = Map::new("lockers");
+
 pub const MINTS_BY_ADDRESS: Map<&[u8], Vec<u64>> = Map::new("mints_by_address");
-pub const WHITELIST_BY_ADDRESS: Map<&[u8], Vec<u64>> = Map::new("whitelist_by_address");
\ No newline at end of file
+pub const WHITELIST_BY_ADDRESS: Map<&[u8], Vec<u64>> = Map::new("whitelist_by_address");
+/// What a character has earned in game, tokens without an entry are level 1
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Progression {
+    pub xp: u64,
+    pub level: u32,
+    // granted on level up, spent by the games
+    pub stat_points: u32,
+}
+
+impl Default for Progression {
+    fn default() -> Self {
+        Progression {
+            xp: 0,
+            level: 1,
+            stat_points: 0,
+        }
+    }
+}
+
+pub const PROGRESSION: Map<&str, Progression> = Map::new("progression");
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct LevelConfig {
+    /// XP needed to reach level 2, 3 and so on, strictly ascending
+    pub thresholds: Vec<u64>,
+    pub stat_points_per_level: u32,
+}
+
+pub const LEVEL_CONFIG: Item<LevelConfig> = Item::new("level_config");
+
+/// Item collections that can be equipped, set by the admin
+pub const ITEM_COLLECTIONS: Map<&[u8], bool> = Map::new("item_collections");
+
+/// An item held by the contract on behalf of a character
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct EquippedItem {
+    pub collection: Addr,
+    pub token_id: String,
+}
+
+/// Equipment of each character, keyed by (character id, slot). It stays with
+/// the character when it changes hands
+pub const EQUIPMENT: Map<(&str, &str), EquippedItem> = Map::new("equipment");
+
+/// Fusion of two characters into a new one, disabled if not set
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FusionConfig {
+    // paid in the payment token, fusion is free if not set
+    pub fee: Option<Uint128>,
+    // chance of rolling a trait from the loot pools instead of a parent
+    pub mutation_rate: Decimal,
+}
+
+pub const FUSION_CONFIG: Item<FusionConfig> = Item::new("fusion_config");
+
+/// Parents of fused tokens, by token id
+pub const LINEAGE: Map<&str, Vec<String>> = Map::new("lineage");
+
+/// Tokens by trait, keyed by (trait type, value, token id)
+pub const TRAIT_INDEX: Map<(&str, &str, &str), bool> = Map::new("trait_index");
+
+/// Number of tokens with each trait, keyed by (trait type, value)
+pub const TRAIT_COUNTS: Map<(&str, &str), u64> = Map::new("trait_counts");
+
+/// Royalty and fee taken from marketplace sales, nothing is taken if not set
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct MarketConfig {
+    pub royalty_rate: Decimal,
+    // the treasury if not set
+    pub royalty_recipient: Option<String>,
+    // goes to the treasury, or the sales balance in escrow mode
+    pub fee_rate: Decimal,
+}
+
+pub const MARKET_CONFIG: Item<MarketConfig> = Item::new("market_config");
+
+/// A token for sale by its owner, for a fixed price
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Listing {
+    pub seller: Addr,
+    pub price: TokenBalance,
+    pub expires: Expiration,
+}
+
+/// Active listings by token id, removed when the token changes hands
+pub const LISTINGS: Map<&str, Listing> = Map::new("listings");
+
+/// What an offer can be accepted for
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OfferTarget {
+    Token { token_id: String },
+    // any token with this trait
+    Trait { trait_type: String, value: String },
+}
+
+/// An escrowed offer, the price is held by the contract until it is accepted
+/// or cancelled
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Offer {
+    pub id: u64,
+    pub bidder: Addr,
+    pub price: TokenBalance,
+    pub target: OfferTarget,
+    pub expires: Expiration,
+}
+
+pub const OFFER_COUNT: Item<u64> = Item::new("offer_count");
+pub const OFFERS: Map<U64Key, Offer> = Map::new("offers");
+/// Indexes of the offers, by (token id, offer id), (trait type, value, offer id)
+/// and (bidder, offer id)
+pub const OFFERS_BY_TOKEN: Map<(&str, U64Key), bool> = Map::new("offers_by_token");
+pub const OFFERS_BY_TRAIT: Map<(&str, &str, U64Key), bool> = Map::new("offers_by_trait");
+pub const OFFERS_BY_BIDDER: Map<(&[u8], U64Key), bool> = Map::new("offers_by_bidder");
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Bid {
+    pub bidder: Addr,
+    pub amount: Uint128,
+}
+
+/// An English auction of a token held by the contract, paid in `payment_token`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Auction {
+    pub payment_token: String,
+    pub reserve_price: Uint128,
+    pub min_increment: Uint128,
+    pub end_time: Timestamp,
+    // bids this close to the end push it back to this long after the bid
+    pub time_extension: u64,
+    pub highest_bid: Option<Bid>,
+    // minted for the auction, handing it out is then part of the mint
+    pub minted: bool,
+}
+
+/// Running auctions by token id
+pub const AUCTIONS: Map<&str, Auction> = Map::new("auctions");
+
+/// Who may use a token in game, set by its owner while ownership stays put
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TokenUser {
+    pub user: Addr,
+    pub expires: Expiration,
+}
+
+/// Users by token id, removed when the token changes hands
+pub const USERS: Map<&str, TokenUser> = Map::new("users");
+
+/// A token for rent by its owner, `price` for `duration` seconds of use
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RentalListing {
+    pub owner: Addr,
+    pub price: TokenBalance,
+    pub duration: u64,
+}
+
+/// Rental listings by token id, removed when the token changes hands
+pub const RENTAL_LISTINGS: Map<&str, RentalListing> = Map::new("rental_listings");
@@ -0,0 +1,200 @@
+use crate::errors::ContractError;
+use crate::state::{Metadata, SvgStyle};
+
+// layout of the character sheet, loot style: one line of text per entry
+const WIDTH: u32 = 350;
+const HEIGHT: u32 = 350;
+const MARGIN: u32 = 10;
+const MIN_FONT_SIZE: u32 = 6;
+const MAX_FONT_SIZE: u32 = 72;
+const MAX_FONT_FAMILY_LEN: usize = 64;
+
+/// Escapes the XML special characters so admin supplied loot strings
+/// can't break out of the text nodes / attributes they are written to
+pub fn escape_xml(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// a hex color or a named one, nothing that could close the CSS rule it is
+// written to
+fn is_css_color(color: &str) -> bool {
+    match color.strip_prefix('#') {
+        Some(hex) => matches!(hex.len(), 3 | 4 | 6 | 8) && hex.chars().all(|c| c.is_ascii_hexdigit()),
+        None => !color.is_empty() && color.len() <= 32 && color.chars().all(|c| c.is_ascii_alphabetic()),
+    }
+}
+
+fn is_font_family(font_family: &str) -> bool {
+    !font_family.trim().is_empty()
+        && font_family.len() <= MAX_FONT_FAMILY_LEN
+        && font_family
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, ' ' | '-' | ','))
+}
+
+/// XML escaping doesn't protect the `<style>` rule, the style values are
+/// checked against what CSS needs instead
+pub fn validate_style(style: &SvgStyle) -> Result<(), ContractError> {
+    let reason = if !(MIN_FONT_SIZE..=MAX_FONT_SIZE).contains(&style.font_size) {
+        format!("font_size must be between {} and {}", MIN_FONT_SIZE, MAX_FONT_SIZE)
+    } else if !is_font_family(&style.font_family) {
+        "font_family may only contain letters, digits, spaces, '-' and ','".to_string()
+    } else if !is_css_color(&style.text_color) {
+        "text_color must be a hex or named color".to_string()
+    } else if !is_css_color(&style.background_color) {
+        "background_color must be a hex or named color".to_string()
+    } else {
+        return Ok(());
+    };
+    Err(ContractError::InvalidConfig { reason })
+}
+
+// opensea stores background_color as a six-character hex without the '#',
+// anything that isn't a color falls back to the style
+fn background(metadata: &Metadata, style: &SvgStyle) -> String {
+    let color = match &metadata.background_color {
+        Some(color) if !color.starts_with('#') => format!("#{}", color),
+        Some(color) => color.clone(),
+        None => return style.background_color.clone(),
+    };
+    if is_css_color(&color) {
+        color
+    } else {
+        style.background_color.clone()
+    }
+}
+
+/// Renders the character sheet of a token: its name followed by every trait
+pub fn render_character_sheet(metadata: &Metadata, style: &SvgStyle) -> String {
+    let mut lines: Vec<String> = vec![];
+    if let Some(name) = &metadata.name {
+        lines.push(name.clone());
+    }
+    for attribute in metadata.attributes.iter().flatten() {
+        lines.push(format!("{}: {}", attribute.trait_type, attribute.value));
+    }
+
+    // stored styles predate the font size bounds, the layout must not overflow
+    let line_height = style.font_size.saturating_mul(3) / 2;
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" preserveAspectRatio=\"xMinYMin meet\" viewBox=\"0 0 {} {}\">",
+        WIDTH, HEIGHT
+    );
+    svg.push_str(&format!(
+        "<style>.base {{ fill: {}; font-family: {}; font-size: {}px; }}</style>",
+        escape_xml(&style.text_color),
+        escape_xml(&style.font_family),
+        style.font_size
+    ));
+    svg.push_str(&format!(
+        "<rect width=\"100%\" height=\"100%\" fill=\"{}\" />",
+        escape_xml(&background(metadata, style))
+    ));
+    for (i, line) in lines.iter().enumerate() {
+        svg.push_str(&format!(
+            "<text x=\"{}\" y=\"{}\" class=\"base\">{}</text>",
+            MARGIN,
+            MARGIN.saturating_add(line_height.saturating_mul(i as u32 + 1)),
+            escape_xml(line)
+        ));
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::Trait;
+
+    #[test]
+    fn escapes_xml_special_characters() {
+        assert_eq!(escape_xml("a & b"), "a &amp; b");
+        assert_eq!(escape_xml("<script>"), "&lt;script&gt;");
+        assert_eq!(escape_xml("\"quoted\" 'single'"), "&quot;quoted&quot; &apos;single&apos;");
+        assert_eq!(escape_xml("Sir Galahad"), "Sir Galahad");
+    }
+
+    #[test]
+    fn markup_in_names_and_traits_stays_text() {
+        let metadata = Metadata {
+            name: Some("<script>alert(1)</script>".to_string()),
+            attributes: Some(vec![Trait {
+                display_type: None,
+                trait_type: "Origin\"><rect".to_string(),
+                value: "</text><image href='x'/>".to_string(),
+            }]),
+            background_color: Some("000\" onload=\"x".to_string()),
+            ..Metadata::default()
+        };
+        let svg = render_character_sheet(&metadata, &SvgStyle::default());
+        assert!(svg.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+        assert!(svg.contains("Origin&quot;&gt;&lt;rect: &lt;/text&gt;&lt;image href=&apos;x&apos;/&gt;"));
+        assert!(!svg.contains("<script"));
+        assert!(!svg.contains("<image"));
+        // an invalid background color falls back to the style
+        assert!(svg.contains("<rect width=\"100%\" height=\"100%\" fill=\"black\" />"));
+        assert_eq!(svg.matches("<text").count(), 2);
+    }
+
+    #[test]
+    fn css_values_are_allowlisted() {
+        let style = SvgStyle {
+            font_family: "Courier New, monospace".to_string(),
+            font_size: 20,
+            text_color: "#ffcc00".to_string(),
+            background_color: "navy".to_string(),
+        };
+        assert_eq!(validate_style(&style), Ok(()));
+
+        let injections = [
+            SvgStyle {
+                text_color: "red;} * {display:none".to_string(),
+                ..style.clone()
+            },
+            SvgStyle {
+                background_color: "#12345g".to_string(),
+                ..style.clone()
+            },
+            SvgStyle {
+                font_family: "serif; } text { fill: red".to_string(),
+                ..style.clone()
+            },
+            SvgStyle {
+                font_size: u32::MAX,
+                ..style.clone()
+            },
+            SvgStyle {
+                font_size: 0,
+                ..style
+            },
+        ];
+        for style in injections {
+            assert!(matches!(validate_style(&style), Err(ContractError::InvalidConfig { .. })));
+        }
+    }
+
+    #[test]
+    fn huge_font_sizes_do_not_overflow() {
+        let style = SvgStyle {
+            font_size: u32::MAX,
+            ..SvgStyle::default()
+        };
+        let metadata = Metadata {
+            name: Some("name".to_string()),
+            ..Metadata::default()
+        };
+        render_character_sheet(&metadata, &style);
+    }
+}
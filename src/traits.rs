@@ -0,0 +1,175 @@
+//! Index of the tokens by trait and trait counts, kept in step with their
+//! stored metadata. Every change to the attributes of a token goes through
+//! `index_traits` and `unindex_traits`.
+use cosmwasm_std::{Decimal, Deps, DepsMut, MessageInfo, Order, Response, StdError, StdResult, Storage};
+use cw721::TokensResponse;
+use cw_storage_plus::{Bound, PrimaryKey};
+
+use crate::errors::ContractError;
+use crate::msg::{RarityResponse, TraitCount, TraitDistributionResponse};
+use crate::roles::assert_role;
+use crate::state::{Extension, LootopiaNFTContract, Role, Trait, TOKENS, TRAIT_COUNTS, TRAIT_INDEX};
+
+fn attributes(extension: &Extension) -> &[Trait] {
+    extension
+        .as_ref()
+        .and_then(|metadata| metadata.attributes.as_deref())
+        .unwrap_or_default()
+}
+
+fn trait_count(storage: &dyn Storage, trait_type: &str, value: &str) -> StdResult<u64> {
+    Ok(TRAIT_COUNTS.may_load(storage, (trait_type, value))?.unwrap_or_default())
+}
+
+// a trait repeated in the attributes is counted once
+pub fn index_traits(storage: &mut dyn Storage, token_id: &str, extension: &Extension) -> StdResult<()> {
+    for t in attributes(extension) {
+        let key = (t.trait_type.as_str(), t.value.as_str(), token_id);
+        if TRAIT_INDEX.may_load(storage, key)?.is_some() {
+            continue;
+        }
+        TRAIT_INDEX.save(storage, key, &true)?;
+        let count = trait_count(storage, &t.trait_type, &t.value)?;
+        TRAIT_COUNTS.save(storage, (&t.trait_type, &t.value), &(count + 1))?;
+    }
+    Ok(())
+}
+
+pub fn unindex_traits(storage: &mut dyn Storage, token_id: &str, extension: &Extension) -> StdResult<()> {
+    for t in attributes(extension) {
+        let key = (t.trait_type.as_str(), t.value.as_str(), token_id);
+        if TRAIT_INDEX.may_load(storage, key)?.is_none() {
+            continue;
+        }
+        TRAIT_INDEX.remove(storage, key);
+        match trait_count(storage, &t.trait_type, &t.value)? {
+            0 | 1 => TRAIT_COUNTS.remove(storage, (&t.trait_type, &t.value)),
+            count => TRAIT_COUNTS.save(storage, (&t.trait_type, &t.value), &(count - 1))?,
+        }
+    }
+    Ok(())
+}
+
+pub fn query_tokens_by_trait(
+    deps: Deps,
+    trait_type: String,
+    value: String,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<TokensResponse> {
+    const MAX_LIMIT: u32 = 30;
+    const DEFAULT_LIMIT: u32 = 10;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+
+    let tokens: Result<Vec<_>, _> = TRAIT_INDEX
+        .prefix((&trait_type, &value))
+        .keys(deps.storage, start, None, Order::Ascending)
+        .map(String::from_utf8)
+        .take(limit)
+        .collect();
+    Ok(TokensResponse { tokens: tokens? })
+}
+
+/// Indexes the traits of a page of tokens, for tokens minted before the
+/// index existed. Indexing a token twice is a no-op, the last token id is
+/// returned to continue from
+pub fn reindex(
+    deps: DepsMut,
+    info: MessageInfo,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> Result<Response, ContractError> {
+    assert_role(deps.storage, &info.sender, Role::Admin)?;
+    const MAX_LIMIT: u32 = 100;
+    const DEFAULT_LIMIT: u32 = 30;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+
+    let tokens: StdResult<Vec<_>> = TOKENS
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .collect();
+    let tokens = tokens?;
+    let mut last_token_id = None;
+    for (token_id, token) in &tokens {
+        let token_id = String::from_utf8(token_id.clone()).map_err(StdError::from)?;
+        index_traits(deps.storage, &token_id, &token.extension)?;
+        last_token_id = Some(token_id);
+    }
+    Ok(Response::new()
+        .add_attribute("action", "reindex")
+        .add_attribute("num_tokens", tokens.len().to_string())
+        .add_attribute("last_token_id", last_token_id.unwrap_or_else(|| "none".to_string())))
+}
+
+pub fn query_trait_distribution(
+    deps: Deps,
+    start_after: Option<(String, String)>,
+    limit: Option<u32>,
+) -> StdResult<TraitDistributionResponse> {
+    const MAX_LIMIT: u32 = 30;
+    const DEFAULT_LIMIT: u32 = 10;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(|(trait_type, value)| {
+        Bound::Exclusive((trait_type.as_str(), value.as_str()).joined_key())
+    });
+
+    let traits: StdResult<Vec<_>> = TRAIT_COUNTS
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (key, count) = item?;
+            let (trait_type, value) = split_key(&key)?;
+            Ok(TraitCount {
+                trait_type,
+                value,
+                count,
+            })
+        })
+        .collect();
+    Ok(TraitDistributionResponse {
+        num_tokens: LootopiaNFTContract::default().token_count(deps.storage)?,
+        traits: traits?,
+    })
+}
+
+/// The score is the sum of the inverse frequencies of the traits of the
+/// token, the counts are returned to rank it against the others off-chain
+pub fn query_rarity(deps: Deps, token_id: String) -> StdResult<RarityResponse> {
+    let token = LootopiaNFTContract::default().tokens.load(deps.storage, &token_id)?;
+    let num_tokens = LootopiaNFTContract::default().token_count(deps.storage)?;
+    let mut score = Decimal::zero();
+    let mut traits = vec![];
+    for t in attributes(&token.extension) {
+        let count = trait_count(deps.storage, &t.trait_type, &t.value)?;
+        if count > 0 {
+            score = score + Decimal::from_ratio(num_tokens, count);
+        }
+        traits.push(TraitCount {
+            trait_type: t.trait_type.clone(),
+            value: t.value.clone(),
+            count,
+        });
+    }
+    Ok(RarityResponse {
+        num_tokens,
+        score,
+        traits,
+    })
+}
+
+// a composite key is stored with its first part length-prefixed
+fn split_key(key: &[u8]) -> StdResult<(String, String)> {
+    if key.len() < 2 {
+        return Err(StdError::generic_err("invalid trait count key"));
+    }
+    let len = u16::from_be_bytes([key[0], key[1]]) as usize;
+    if key.len() < 2 + len {
+        return Err(StdError::generic_err("invalid trait count key"));
+    }
+    Ok((
+        String::from_utf8(key[2..2 + len].to_vec())?,
+        String::from_utf8(key[2 + len..].to_vec())?,
+    ))
+}
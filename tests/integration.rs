@@ -0,0 +1,544 @@
+//! End-to-end tests of the sale: a real cw20-base token pays for characters
+//! minted by this contract, both running in cw-multi-test.
+use cosmwasm_std::testing::{mock_env, MockApi, MockStorage};
+use cosmwasm_std::{to_binary, Addr, Decimal, Empty, Uint128};
+use cw20::{BalanceResponse, Cw20Coin, Cw20ExecuteMsg, Cw20QueryMsg};
+use cw721::{NftInfoResponse, OwnerOfResponse};
+use cw_multi_test::{App, AppResponse, BankKeeper, Contract, ContractWrapper, Executor};
+
+use cw721_ext::contract::{execute, instantiate, query};
+use cw721_ext::errors::ContractError;
+use cw721_ext::msg::{AuctionLot, UserOfResponse, ExecuteMsg, InstantiateMsg, MintMsg, QueryMsg, ReceiveMsg, OffersResponse, SalesBalanceResponse};
+use cw721_ext::state::{Extension, MarketConfig, OfferTarget, RentalListing};
+
+const ADMIN: &str = "admin";
+const TREASURY: &str = "treasury";
+const MINTER: &str = "minter";
+const PRICE: u128 = 100;
+
+fn cw20_contract() -> Box<dyn Contract<Empty>> {
+    Box::new(ContractWrapper::new(
+        cw20_base::contract::execute,
+        cw20_base::contract::instantiate,
+        cw20_base::contract::query,
+    ))
+}
+
+fn lootopia_contract() -> Box<dyn Contract<Empty>> {
+    Box::new(ContractWrapper::new(execute, instantiate, query))
+}
+
+fn loot_list(prefix: &str) -> Vec<String> {
+    (1..=3).map(|i| format!("{} {}", prefix, i)).collect()
+}
+
+struct Suite {
+    app: App,
+    token: Addr,
+    nft: Addr,
+}
+
+impl Suite {
+    fn new(num_items: u64, limit_per_address: u64, whitelist: bool, escrow: bool) -> Self {
+        let mut app = App::new(MockApi::default(), mock_env().block, BankKeeper::new(), MockStorage::new());
+        let cw20_id = app.store_code(cw20_contract());
+        let lootopia_id = app.store_code(lootopia_contract());
+
+        let token = app
+            .instantiate_contract(
+                cw20_id,
+                Addr::unchecked(ADMIN),
+                &cw20_base::msg::InstantiateMsg {
+                    name: "Lootopia Gold".to_string(),
+                    symbol: "GOLD".to_string(),
+                    decimals: 6,
+                    initial_balances: ["alice", "bob", ADMIN]
+                        .iter()
+                        .map(|address| Cw20Coin {
+                            address: address.to_string(),
+                            amount: Uint128::from(10 * PRICE),
+                        })
+                        .collect(),
+                    mint: None,
+                    marketing: None,
+                },
+                &[],
+                "gold",
+                None,
+            )
+            .unwrap();
+
+        let nft = app
+            .instantiate_contract(
+                lootopia_id,
+                Addr::unchecked(ADMIN),
+                &InstantiateMsg {
+                    name: "Lootopia".to_string(),
+                    symbol: "LOOT".to_string(),
+                    // not the payment token, the sale mints as the stored minter
+                    minter: MINTER.to_string(),
+                    payment_token: token.to_string(),
+                    price: Uint128::from(PRICE),
+                    treasury: TREASURY.to_string(),
+                    limit_per_address,
+                    names: loot_list("name"),
+                    origins: loot_list("origin"),
+                    professions: loot_list("profession"),
+                    obsessions: loot_list("obsession"),
+                    talents: loot_list("talent"),
+                    skills: loot_list("skill"),
+                    alignments: loot_list("alignment"),
+                    num_items,
+                    num_reserved: None,
+                    whitelist,
+                    whitelist_admin: ADMIN.to_string(),
+                    admin: ADMIN.to_string(),
+                    svg_style: None,
+                    escrow: Some(escrow),
+                    sale_start: None,
+                    soulbound: None,
+                },
+                &[],
+                "lootopia",
+                None,
+            )
+            .unwrap();
+
+        Suite { app, token, nft }
+    }
+
+    fn buy(&mut self, buyer: &str) -> anyhow::Result<AppResponse> {
+        let mint_msg = MintMsg {
+            token_id: String::new(),
+            owner: buyer.to_string(),
+            token_uri: None,
+            extension: None,
+        };
+        self.send(
+            buyer,
+            PRICE,
+            &ReceiveMsg::Buy {
+                mint_msg: Box::new(mint_msg),
+            },
+        )
+    }
+
+    // pays the contract with a cw20 Send
+    fn send(&mut self, sender: &str, amount: u128, msg: &ReceiveMsg) -> anyhow::Result<AppResponse> {
+        self.app.execute_contract(
+            Addr::unchecked(sender),
+            self.token.clone(),
+            &Cw20ExecuteMsg::Send {
+                contract: self.nft.to_string(),
+                amount: Uint128::from(amount),
+                msg: to_binary(msg).unwrap(),
+            },
+            &[],
+        )
+    }
+
+    fn execute(&mut self, sender: &str, msg: &ExecuteMsg) -> anyhow::Result<AppResponse> {
+        self.app
+            .execute_contract(Addr::unchecked(sender), self.nft.clone(), msg, &[])
+    }
+
+    fn balance(&self, address: &str) -> Uint128 {
+        let response: BalanceResponse = self
+            .app
+            .wrap()
+            .query_wasm_smart(
+                &self.token,
+                &Cw20QueryMsg::Balance {
+                    address: address.to_string(),
+                },
+            )
+            .unwrap();
+        response.balance
+    }
+
+    fn nft_info(&self, token_id: &str) -> NftInfoResponse<Extension> {
+        self.app
+            .wrap()
+            .query_wasm_smart(
+                &self.nft,
+                &QueryMsg::NftInfo {
+                    token_id: token_id.to_string(),
+                },
+            )
+            .unwrap()
+    }
+}
+
+fn contract_error(err: anyhow::Error) -> ContractError {
+    err.downcast().unwrap()
+}
+
+#[test]
+fn buying_pays_the_treasury_and_mints_a_character() {
+    let mut suite = Suite::new(10, 5, false, false);
+    suite.buy("alice").unwrap();
+
+    assert_eq!(suite.balance(TREASURY), Uint128::from(PRICE));
+    assert_eq!(suite.balance("alice"), Uint128::from(9 * PRICE));
+
+    let info = suite.nft_info("1");
+    let metadata = info.extension.unwrap();
+    assert!(loot_list("name").contains(&metadata.name.unwrap()));
+    let attributes = metadata.attributes.unwrap();
+    let trait_types: Vec<_> = attributes.iter().map(|t| t.trait_type.as_str()).collect();
+    assert_eq!(
+        trait_types,
+        vec!["Origin", "Profession", "Obsession", "Talent", "Skill", "Alignment", "Level"]
+    );
+    assert_eq!(attributes[6].display_type, Some("number".to_string()));
+    assert_eq!(attributes[6].value, "1");
+    assert!(loot_list("profession").contains(&attributes[1].value));
+}
+
+#[test]
+fn escrowed_sales_are_withdrawn_by_the_treasurer() {
+    let mut suite = Suite::new(10, 5, false, true);
+    suite.buy("alice").unwrap();
+    suite.buy("bob").unwrap();
+    assert_eq!(suite.balance(TREASURY), Uint128::zero());
+    assert_eq!(suite.balance(suite.nft.as_str()), Uint128::from(2 * PRICE));
+
+    let withdraw = ExecuteMsg::WithdrawSales {
+        amount: Uint128::from(2 * PRICE),
+        payment_token: None,
+    };
+    let err = suite.execute("alice", &withdraw).unwrap_err();
+    assert_eq!(contract_error(err), ContractError::Unauthorized {});
+
+    suite.execute(ADMIN, &withdraw).unwrap();
+    assert_eq!(suite.balance(TREASURY), Uint128::from(2 * PRICE));
+
+    let err = suite.execute(ADMIN, &withdraw).unwrap_err();
+    assert_eq!(
+        contract_error(err),
+        ContractError::InsufficientSalesBalance {
+            available: Uint128::zero(),
+            requested: Uint128::from(2 * PRICE),
+        }
+    );
+    let sales: SalesBalanceResponse = suite
+        .app
+        .wrap()
+        .query_wasm_smart(&suite.nft, &QueryMsg::SalesBalance {})
+        .unwrap();
+    assert_eq!(sales.balances[0].amount, Uint128::zero());
+}
+
+#[test]
+fn whitelist_is_enforced() {
+    let mut suite = Suite::new(10, 5, true, false);
+    let err = suite.buy("alice").unwrap_err();
+    assert_eq!(contract_error(err), ContractError::NotWhitelisted {});
+    // the failed purchase is rolled back
+    assert_eq!(suite.balance("alice"), Uint128::from(10 * PRICE));
+
+    suite
+        .execute(
+            ADMIN,
+            &ExecuteMsg::AddWhitelistAddresses {
+                addresses: vec!["alice".to_string()],
+            },
+        )
+        .unwrap();
+    suite.buy("alice").unwrap();
+    let err = suite.buy("bob").unwrap_err();
+    assert_eq!(contract_error(err), ContractError::NotWhitelisted {});
+
+    suite
+        .execute(ADMIN, &ExecuteMsg::ToggleWhitelist { whitelist: false })
+        .unwrap();
+    suite.buy("bob").unwrap();
+}
+
+#[test]
+fn mints_per_address_are_limited() {
+    let mut suite = Suite::new(10, 2, false, false);
+    suite.buy("alice").unwrap();
+    suite.buy("alice").unwrap();
+    let err = suite.buy("alice").unwrap_err();
+    assert_eq!(contract_error(err), ContractError::MaxMintsPerAddress {});
+    suite.buy("bob").unwrap();
+}
+
+#[test]
+fn supply_cap_holds_end_to_end() {
+    let mut suite = Suite::new(3, 5, false, false);
+    suite.buy("alice").unwrap();
+    suite.buy("bob").unwrap();
+    suite.buy(ADMIN).unwrap();
+    assert_eq!(suite.nft_info("3").token_uri, Some("3".to_string()));
+
+    let err = suite.buy("alice").unwrap_err();
+    assert_eq!(contract_error(err), ContractError::SoldOut {});
+    assert_eq!(suite.balance(TREASURY), Uint128::from(3 * PRICE));
+}
+
+#[test]
+fn listed_characters_are_bought_with_royalty_and_fee() {
+    let mut suite = Suite::new(10, 5, false, false);
+    suite.buy("alice").unwrap();
+    let market_config = MarketConfig {
+        royalty_rate: Decimal::percent(10),
+        royalty_recipient: None,
+        fee_rate: Decimal::percent(5),
+    };
+    suite
+        .execute(ADMIN, &ExecuteMsg::SetMarketConfig { market_config })
+        .unwrap();
+    let list = ExecuteMsg::ListToken {
+        token_id: "1".to_string(),
+        price: Uint128::from(2 * PRICE),
+        expires: None,
+    };
+    suite.execute("alice", &list).unwrap();
+
+    let buy_listing = ReceiveMsg::BuyListing {
+        token_id: "1".to_string(),
+    };
+    let err = suite.send("bob", PRICE, &buy_listing).unwrap_err();
+    assert_eq!(
+        contract_error(err),
+        ContractError::InsufficientPayment {
+            required: Uint128::from(2 * PRICE),
+            sent: Uint128::from(PRICE),
+        }
+    );
+    suite.send("bob", 2 * PRICE, &buy_listing).unwrap();
+
+    let owner: OwnerOfResponse = suite
+        .app
+        .wrap()
+        .query_wasm_smart(
+            &suite.nft,
+            &QueryMsg::OwnerOf {
+                token_id: "1".to_string(),
+                include_expired: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(owner.owner, "bob");
+    // the sale, then 10% royalty and 5% fee of the listing price
+    assert_eq!(suite.balance(TREASURY), Uint128::from(PRICE + 30));
+    assert_eq!(suite.balance("alice"), Uint128::from(9 * PRICE + 170));
+    assert_eq!(suite.balance("bob"), Uint128::from(8 * PRICE));
+
+    // the listing went with the sale
+    let err = suite.send("alice", 2 * PRICE, &buy_listing).unwrap_err();
+    assert_eq!(
+        contract_error(err),
+        ContractError::NotListed {
+            token_id: "1".to_string()
+        }
+    );
+}
+
+#[test]
+fn offers_are_escrowed_until_accepted_or_cancelled() {
+    let mut suite = Suite::new(10, 5, false, false);
+    suite.buy("alice").unwrap();
+    let profession = suite.nft_info("1").extension.unwrap().attributes.unwrap()[1].clone();
+
+    // an offer for any character with the same profession, and one for the token
+    let on_trait = ReceiveMsg::MakeOffer {
+        target: OfferTarget::Trait {
+            trait_type: profession.trait_type,
+            value: profession.value,
+        },
+        expires: None,
+    };
+    suite.send("bob", 3 * PRICE, &on_trait).unwrap();
+    let on_token = ReceiveMsg::MakeOffer {
+        target: OfferTarget::Token {
+            token_id: "1".to_string(),
+        },
+        expires: None,
+    };
+    suite.send("bob", 2 * PRICE, &on_token).unwrap();
+    assert_eq!(suite.balance("bob"), Uint128::from(5 * PRICE));
+
+    let cancel = ExecuteMsg::CancelOffer { offer_id: 2 };
+    let err = suite.execute("alice", &cancel).unwrap_err();
+    assert_eq!(contract_error(err), ContractError::Unauthorized {});
+    suite.execute("bob", &cancel).unwrap();
+    assert_eq!(suite.balance("bob"), Uint128::from(7 * PRICE));
+
+    let accept = ExecuteMsg::AcceptOffer {
+        offer_id: 1,
+        token_id: "1".to_string(),
+    };
+    let err = suite.execute("bob", &accept).unwrap_err();
+    assert_eq!(contract_error(err), ContractError::Unauthorized {});
+    suite.execute("alice", &accept).unwrap();
+    assert_eq!(suite.balance("alice"), Uint128::from(12 * PRICE));
+    assert_eq!(suite.balance(suite.nft.as_str()), Uint128::zero());
+
+    let offers: OffersResponse = suite
+        .app
+        .wrap()
+        .query_wasm_smart(
+            &suite.nft,
+            &QueryMsg::OffersByBidder {
+                bidder: "bob".to_string(),
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    assert!(offers.offers.is_empty());
+    let owner: OwnerOfResponse = suite
+        .app
+        .wrap()
+        .query_wasm_smart(
+            &suite.nft,
+            &QueryMsg::OwnerOf {
+                token_id: "1".to_string(),
+                include_expired: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(owner.owner, "bob");
+}
+
+#[test]
+fn auctions_refund_the_outbid_and_pay_the_treasury() {
+    let mut suite = Suite::new(10, 5, false, false);
+    suite.buy(ADMIN).unwrap();
+    let start = suite.app.block_info().time;
+    suite
+        .execute(
+            ADMIN,
+            &ExecuteMsg::StartAuction {
+                lot: AuctionLot::Token {
+                    token_id: "1".to_string(),
+                },
+                reserve_price: Uint128::from(2 * PRICE),
+                min_increment: Uint128::from(PRICE),
+                end_time: start.plus_seconds(3600),
+                time_extension: Some(600),
+            },
+        )
+        .unwrap();
+
+    let bid = ReceiveMsg::Bid {
+        token_id: "1".to_string(),
+    };
+    suite.send("alice", 2 * PRICE, &bid).unwrap();
+    let err = suite.send("bob", 2 * PRICE + 50, &bid).unwrap_err();
+    assert_eq!(
+        contract_error(err),
+        ContractError::InsufficientPayment {
+            required: Uint128::from(3 * PRICE),
+            sent: Uint128::from(2 * PRICE + 50),
+        }
+    );
+
+    // a bid in the last minutes extends the auction
+    suite.app.update_block(|block| block.time = block.time.plus_seconds(3300));
+    suite.send("bob", 3 * PRICE, &bid).unwrap();
+    assert_eq!(suite.balance("alice"), Uint128::from(10 * PRICE));
+    assert_eq!(suite.balance("bob"), Uint128::from(7 * PRICE));
+
+    let settle = ExecuteMsg::SettleAuction {
+        token_id: "1".to_string(),
+    };
+    suite.app.update_block(|block| block.time = block.time.plus_seconds(300));
+    let err = suite.execute("bob", &settle).unwrap_err();
+    assert_eq!(
+        contract_error(err),
+        ContractError::AuctionNotEnded {
+            end_time: start.plus_seconds(3900)
+        }
+    );
+    suite.app.update_block(|block| block.time = block.time.plus_seconds(300));
+    suite.execute("alice", &settle).unwrap();
+
+    assert_eq!(suite.balance(TREASURY), Uint128::from(4 * PRICE));
+    assert_eq!(suite.balance(suite.nft.as_str()), Uint128::zero());
+    let owner: OwnerOfResponse = suite
+        .app
+        .wrap()
+        .query_wasm_smart(
+            &suite.nft,
+            &QueryMsg::OwnerOf {
+                token_id: "1".to_string(),
+                include_expired: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(owner.owner, "bob");
+}
+
+#[test]
+fn rented_characters_stay_with_the_renter_until_expiry() {
+    let mut suite = Suite::new(10, 5, false, false);
+    suite.buy("alice").unwrap();
+    suite
+        .execute(
+            "alice",
+            &ExecuteMsg::ListRental {
+                token_id: "1".to_string(),
+                price: Uint128::from(PRICE),
+                duration: 3600,
+            },
+        )
+        .unwrap();
+    let rent = ReceiveMsg::Rent {
+        token_id: "1".to_string(),
+    };
+    suite.send("bob", PRICE, &rent).unwrap();
+    assert_eq!(suite.balance("alice"), Uint128::from(10 * PRICE));
+
+    let user_of = |suite: &Suite| -> UserOfResponse {
+        suite
+            .app
+            .wrap()
+            .query_wasm_smart(
+                &suite.nft,
+                &QueryMsg::UserOf {
+                    token_id: "1".to_string(),
+                },
+            )
+            .unwrap()
+    };
+    assert_eq!(user_of(&suite).user, Some("bob".to_string()));
+
+    // neither the owner nor another renter can take it from bob
+    let err = suite.send("admin", PRICE, &rent).unwrap_err();
+    assert_eq!(
+        contract_error(err),
+        ContractError::TokenRented {
+            token_id: "1".to_string()
+        }
+    );
+    let transfer = ExecuteMsg::TransferNft {
+        recipient: "admin".to_string(),
+        token_id: "1".to_string(),
+    };
+    let err = suite.execute("alice", &transfer).unwrap_err();
+    assert_eq!(
+        contract_error(err),
+        ContractError::TokenLocked {
+            token_id: "1".to_string()
+        }
+    );
+
+    suite.app.update_block(|block| block.time = block.time.plus_seconds(3600));
+    assert_eq!(user_of(&suite).user, None);
+    suite.execute("alice", &transfer).unwrap();
+    let listing: Option<RentalListing> = suite
+        .app
+        .wrap()
+        .query_wasm_smart(
+            &suite.nft,
+            &QueryMsg::RentalListing {
+                token_id: "1".to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(listing, None);
+}